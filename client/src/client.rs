@@ -62,7 +62,11 @@ impl ClientApp {
     fn update(&mut self, dt: std::time::Duration) {
         println!("Client update: dt={:?}", dt);
         match self.world.as_mut() {
-            Some(world) => { self.engine.update(world, dt); }
+            Some(world) => {
+                if let Err(error) = self.engine.update(world, dt) {
+                    println!("World update failed: {:?}", error);
+                }
+            }
             _ => {}
         }
     }
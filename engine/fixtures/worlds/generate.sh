@@ -0,0 +1,66 @@
+#!/bin/sh
+# Rebuilds the fixture worlds with the engine versions that wrote them, see README.md.
+# Usage: engine/fixtures/worlds/generate.sh [version...], all of them by default.
+set -e
+
+repo=$(git -C "$(dirname "$0")" rev-parse --show-toplevel)
+out=$repo/engine/fixtures/worlds
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+# version, commit that wrote it, name of the grass block at that commit
+generate() {
+    version=$1
+    commit=$2
+    grass=$3
+    tree=$work/$version
+    mkdir -p "$tree"
+    git -C "$repo" archive "$commit" engine | tar -x -C "$tree"
+    # Newer compilers reject the trailing semicolon in the macro of old versions
+    sed -i 's/^        })();$/        })()/' "$tree/engine/src/features.rs"
+    cat >> "$tree/engine/src/model/world.rs" <<'EOF'
+
+#[cfg(test)]
+mod fixture {
+    use super::*;
+    use crate::model::block_entity::{BlockEntity, ItemStack};
+
+    #[test]
+    fn write_fixture() {
+        let dir = PathBuf::from(std::env::var("FIXTURE_DIR").unwrap());
+        let grass = std::env::var("FIXTURE_GRASS").unwrap();
+        let mut world = World::new();
+        let registry = world.registry().clone();
+        world.level_mut().seed = 42;
+        world.level_mut().spawn = BlockPos::new(0, 0, 11);
+        world.level_mut().time = 12345;
+        world.update_blocks_in(BlockBox::new(BlockPos::new(-3, -3, 0), BlockPos::new(3, 3, 10)), |pos, _| {
+            return Some(registry.block(if pos.z == 10 { &grass } else if pos.z > 6 { "dirt" } else { "stone" }).unwrap());
+        });
+        world.set_block(BlockPos::new(2, 2, 11), registry.block("lever").unwrap().with(&registry, "powered", "true").unwrap().with(&registry, "facing", "west").unwrap());
+        world.set_block(BlockPos::new(-3, -3, 11), registry.block("chest").unwrap().with(&registry, "facing", "east").unwrap());
+        if let Some(BlockEntity::Chest(chest)) = world.map_mut().loaded_chunk_mut(ChunkPos::new(-1, -1)).unwrap().block_entity_mut(BlockPos::new(-3, -3, 11)) {
+            chest.slots[0] = Some(ItemStack::new(&grass, 5));
+        }
+        world.set_block(BlockPos::new(200, -100, 40), registry.block(&grass).unwrap());
+        world.join_player("alice").health = 12.0;
+        world.save(&dir).unwrap();
+    }
+}
+EOF
+    (cd "$tree/engine" && FIXTURE_DIR="$tree/world" FIXTURE_GRASS="$grass" cargo test --quiet --lib fixture::write_fixture)
+    # Worlds that were never played since the upgrade
+    sed -i 's/last_played: [0-9]*/last_played: 0/' "$tree/world/level.ron"
+    rm -rf "${out:?}/$version"
+    cp -r "$tree/world" "$out/$version"
+}
+
+[ $# -eq 0 ] && set -- v1 v2 v3
+for version in "$@"; do
+    case $version in
+        v1) generate v1 2346518 grass ;;
+        v2) generate v2 d799734 grass ;;
+        v3) generate v3 a5693f8 grass_block ;;
+        *) echo "unknown fixture $version" >&2; exit 1 ;;
+    esac
+done
@@ -1,4 +1,6 @@
 mod types;
+mod position;
 pub mod math;
 
-pub use types::*;
\ No newline at end of file
+pub use types::*;
+pub use position::*;
\ No newline at end of file
@@ -0,0 +1,282 @@
+use crate::core::types::*;
+
+use nalgebra::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+use std::ops::Add;
+
+// Edge length of a sub-chunk and width of a chunk column, in blocks
+pub const SUBCHUNK_SIZE: SizeScalar = 16;
+const SIGNED_SIZE: PosScalar = SUBCHUNK_SIZE as PosScalar;
+
+// World position of a block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BlockPos {
+    pub x: PosScalar,
+    pub y: PosScalar,
+    pub z: PosScalar,
+}
+
+// Position of a chunk column, in chunks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ChunkPos {
+    pub x: PosScalar,
+    pub y: PosScalar,
+}
+
+// Position of a sub-chunk, in sub-chunks. z is the index of the sub-chunk in its column.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SubChunkPos {
+    pub x: PosScalar,
+    pub y: PosScalar,
+    pub z: PosScalar,
+}
+
+// Position of a block inside of its sub-chunk, every coordinate is in [0, SUBCHUNK_SIZE).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LocalPos {
+    pub x: u8,
+    pub y: u8,
+    pub z: u8,
+}
+
+// Axis aligned box of blocks, both corners are inclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockBox {
+    pub min: BlockPos,
+    pub max: BlockPos,
+}
+
+impl BlockPos {
+    pub fn new(x: PosScalar, y: PosScalar, z: PosScalar) -> Self {
+        return BlockPos{ x, y, z };
+    }
+
+    pub fn chunk(&self) -> ChunkPos {
+        return ChunkPos::new(self.x.div_euclid(SIGNED_SIZE), self.y.div_euclid(SIGNED_SIZE));
+    }
+
+    pub fn subchunk(&self) -> SubChunkPos {
+        return SubChunkPos::new(
+            self.x.div_euclid(SIGNED_SIZE),
+            self.y.div_euclid(SIGNED_SIZE),
+            self.z.div_euclid(SIGNED_SIZE));
+    }
+
+    pub fn local(&self) -> LocalPos {
+        return LocalPos::new(
+            self.x.rem_euclid(SIGNED_SIZE) as u8,
+            self.y.rem_euclid(SIGNED_SIZE) as u8,
+            self.z.rem_euclid(SIGNED_SIZE) as u8);
+    }
+
+    pub fn to_vector(&self) -> Vector3<PosScalar> {
+        return Vector3::new(self.x, self.y, self.z);
+    }
+}
+
+impl From<Vector3<PosScalar>> for BlockPos {
+    fn from(v: Vector3<PosScalar>) -> Self {
+        return BlockPos::new(v.x, v.y, v.z);
+    }
+}
+
+impl Add<Vector3<PosScalar>> for BlockPos {
+    type Output = BlockPos;
+
+    fn add(self, offset: Vector3<PosScalar>) -> BlockPos {
+        return BlockPos::new(self.x + offset.x, self.y + offset.y, self.z + offset.z);
+    }
+}
+
+impl BlockBox {
+    // Any two opposite corners.
+    pub fn new(a: BlockPos, b: BlockPos) -> Self {
+        return BlockBox{
+            min: BlockPos::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: BlockPos::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        };
+    }
+
+    pub fn contains(&self, pos: BlockPos) -> bool {
+        return pos.x >= self.min.x && pos.y >= self.min.y && pos.z >= self.min.z
+            && pos.x <= self.max.x && pos.y <= self.max.y && pos.z <= self.max.z;
+    }
+
+    pub fn intersection(&self, other: &BlockBox) -> Option<BlockBox> {
+        let min = BlockPos::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z));
+        let max = BlockPos::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z));
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            return None;
+        }
+        return Some(BlockBox{ min, max });
+    }
+
+    pub fn volume(&self) -> usize {
+        return (self.max.x - self.min.x + 1) as usize * (self.max.y - self.min.y + 1) as usize * (self.max.z - self.min.z + 1) as usize;
+    }
+
+    // Chunk columns touched by the box.
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkPos> {
+        let (min, max) = (self.min.chunk(), self.max.chunk());
+        return (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| ChunkPos::new(x, y)));
+    }
+
+    // x first, then y, then z.
+    pub fn positions(&self) -> impl Iterator<Item = BlockPos> {
+        let (min, max) = (self.min, self.max);
+        return (min.z..=max.z).flat_map(move |z|
+            (min.y..=max.y).flat_map(move |y|
+                (min.x..=max.x).map(move |x| BlockPos::new(x, y, z))));
+    }
+}
+
+impl ChunkPos {
+    pub fn new(x: PosScalar, y: PosScalar) -> Self {
+        return ChunkPos{ x, y };
+    }
+
+    // Lowest corner of the chunk, at z = 0.
+    pub fn origin(&self) -> BlockPos {
+        return BlockPos::new(self.x * SIGNED_SIZE, self.y * SIGNED_SIZE, 0);
+    }
+
+    pub fn subchunk(&self, index: PosScalar) -> SubChunkPos {
+        return SubChunkPos::new(self.x, self.y, index);
+    }
+
+    // Column of the chunk, between the given heights.
+    pub fn column(&self, min_z: PosScalar, max_z: PosScalar) -> BlockBox {
+        let origin = self.origin();
+        let max = origin + Vector3::new(SIGNED_SIZE - 1, SIGNED_SIZE - 1, max_z);
+        return BlockBox::new(BlockPos::new(origin.x, origin.y, min_z), max);
+    }
+
+    pub fn to_vector(&self) -> Vector2<PosScalar> {
+        return Vector2::new(self.x, self.y);
+    }
+}
+
+impl From<Vector2<PosScalar>> for ChunkPos {
+    fn from(v: Vector2<PosScalar>) -> Self {
+        return ChunkPos::new(v.x, v.y);
+    }
+}
+
+impl SubChunkPos {
+    pub fn new(x: PosScalar, y: PosScalar, z: PosScalar) -> Self {
+        return SubChunkPos{ x, y, z };
+    }
+
+    pub fn chunk(&self) -> ChunkPos {
+        return ChunkPos::new(self.x, self.y);
+    }
+
+    pub fn origin(&self) -> BlockPos {
+        return BlockPos::new(self.x * SIGNED_SIZE, self.y * SIGNED_SIZE, self.z * SIGNED_SIZE);
+    }
+
+    pub fn block(&self, local: LocalPos) -> BlockPos {
+        return self.origin() + Vector3::new(local.x as PosScalar, local.y as PosScalar, local.z as PosScalar);
+    }
+
+    pub fn bounds(&self) -> BlockBox {
+        let origin = self.origin();
+        return BlockBox{ min: origin, max: origin + Vector3::new(SIGNED_SIZE - 1, SIGNED_SIZE - 1, SIGNED_SIZE - 1) };
+    }
+}
+
+impl LocalPos {
+    pub const VOLUME: usize = (SUBCHUNK_SIZE * SUBCHUNK_SIZE * SUBCHUNK_SIZE) as usize;
+
+    pub fn new(x: u8, y: u8, z: u8) -> Self {
+        debug_assert!((x as SizeScalar) < SUBCHUNK_SIZE && (y as SizeScalar) < SUBCHUNK_SIZE && (z as SizeScalar) < SUBCHUNK_SIZE);
+        return LocalPos{ x, y, z };
+    }
+
+    // x major, then y, then z layers.
+    pub fn index(&self) -> usize {
+        let size = SUBCHUNK_SIZE as usize;
+        return (self.z as usize * size + self.y as usize) * size + self.x as usize;
+    }
+
+    pub fn from_index(index: usize) -> Self {
+        let size = SUBCHUNK_SIZE as usize;
+        assert!(index < LocalPos::VOLUME);
+        return LocalPos::new((index % size) as u8, (index / size % size) as u8, (index / (size * size)) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_in_every_quadrant() {
+        let cases = [
+            // (block, chunk, local)
+            ((0, 0, 0), (0, 0, 0), (0, 0, 0)),
+            ((15, 15, 15), (0, 0, 0), (15, 15, 15)),
+            ((16, 17, 18), (1, 1, 1), (0, 1, 2)),
+            ((-1, 0, 0), (-1, 0, 0), (15, 0, 0)),
+            ((0, -1, 0), (0, -1, 0), (0, 15, 0)),
+            ((-1, -1, -1), (-1, -1, -1), (15, 15, 15)),
+            ((-16, -17, 5), (-1, -2, 0), (0, 15, 5)),
+            ((-33, 40, -16), (-3, 2, -1), (15, 8, 0)),
+            ((40, -33, 300), (2, -3, 18), (8, 15, 12)),
+        ];
+        for ((bx, by, bz), (cx, cy, cz), (lx, ly, lz)) in cases.iter() {
+            let pos = BlockPos::new(*bx, *by, *bz);
+            assert_eq!(pos.chunk(), ChunkPos::new(*cx, *cy), "{:?}", pos);
+            assert_eq!(pos.subchunk(), SubChunkPos::new(*cx, *cy, *cz), "{:?}", pos);
+            assert_eq!(pos.local(), LocalPos::new(*lx, *ly, *lz), "{:?}", pos);
+            assert_eq!(pos.subchunk().block(pos.local()), pos);
+        }
+    }
+
+    #[test]
+    fn chunk_origins_round_trip() {
+        for x in -3..3 {
+            for y in -3..3 {
+                let chunk = ChunkPos::new(x, y);
+                assert_eq!(chunk.origin().chunk(), chunk);
+                assert_eq!((chunk.origin() + Vector3::new(-1, -1, 0)).chunk(), ChunkPos::new(x - 1, y - 1));
+                assert_eq!((chunk.origin() + Vector3::new(SIGNED_SIZE, SIGNED_SIZE, 0)).chunk(), ChunkPos::new(x + 1, y + 1));
+                assert_eq!(chunk.subchunk(2).origin(), chunk.origin() + Vector3::new(0, 0, 2 * SIGNED_SIZE));
+            }
+        }
+    }
+
+    #[test]
+    fn boxes_cover_their_chunks() {
+        let bounds = BlockBox::new(BlockPos::new(5, -20, 3), BlockPos::new(-17, 0, 1));
+        assert_eq!(bounds.min, BlockPos::new(-17, -20, 1));
+        assert_eq!(bounds.max, BlockPos::new(5, 0, 3));
+        assert_eq!(bounds.volume(), 23 * 21 * 3);
+        assert_eq!(bounds.positions().count(), bounds.volume());
+        assert!(bounds.positions().all(|p| bounds.contains(p)));
+
+        let chunks: Vec<_> = bounds.chunks().collect();
+        assert_eq!(chunks.len(), 3 * 3);
+        for x in -2..=0 {
+            for y in -2..=0 {
+                assert!(chunks.contains(&ChunkPos::new(x, y)));
+            }
+        }
+        let column = ChunkPos::new(-1, 2).column(0, 255);
+        assert_eq!(column.volume(), 16 * 16 * 256);
+        assert_eq!(column.intersection(&bounds), None);
+        let clipped = ChunkPos::new(-1, -1).column(0, 255).intersection(&bounds).unwrap();
+        assert_eq!(clipped, BlockBox::new(BlockPos::new(-16, -16, 1), BlockPos::new(-1, -1, 3)));
+        assert_eq!(SubChunkPos::new(-1, 0, 2).bounds().positions().count(), LocalPos::VOLUME);
+    }
+
+    #[test]
+    fn local_index_round_trips() {
+        for index in 0..LocalPos::VOLUME {
+            assert_eq!(LocalPos::from_index(index).index(), index);
+        }
+        assert_eq!(LocalPos::new(1, 0, 0).index(), 1);
+        assert_eq!(LocalPos::new(0, 1, 0).index(), SUBCHUNK_SIZE as usize);
+        assert_eq!(LocalPos::new(15, 15, 15).index(), LocalPos::VOLUME - 1);
+    }
+}
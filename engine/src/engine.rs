@@ -1,4 +1,4 @@
-use crate::model::world::World;
+use crate::model::world::{World, WorldError};
 
 
 pub struct Engine {
@@ -8,7 +8,11 @@ pub struct Engine {
 impl Engine {
     pub fn new() -> Self { return Engine{}; }
 
-    pub fn update(&mut self, world: &mut World, dt: std::time::Duration) {
-
+    pub fn update(&mut self, world: &mut World, dt: std::time::Duration) -> Result<(), WorldError> {
+        // The chunks that did load are simulated even if others failed
+        let chunks = world.update_chunks();
+        world.advance_time(dt);
+        world.tick_block_entities(dt);
+        return chunks;
     }
 }
\ No newline at end of file
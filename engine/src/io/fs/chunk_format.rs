@@ -0,0 +1,433 @@
+// Binary encoding of a single chunk. All integers are little endian,
+// strings are a u16 byte length followed by UTF-8 data.
+//
+// Version 3:
+//   header          magic "RCCH", version u16
+//   position        x i32, y i32
+//   block table     count u16, then for every entry:
+//                     name str, property count u8, (property str, value str) pairs
+//   sub-chunks      count u8, then for every allocated sub-chunk:
+//                     index u8 (from the bottom of the chunk)
+//                     palette length u16, for every entry a u32 made of
+//                       block table index (bits 0-15) and visible faces (bits 16-21)
+//                     bits per index u8, 0 for a single entry palette, otherwise
+//                       word count u16 followed by u64 words
+//                     light flags u8 (1: sky light, 2: block light), then 2048 bytes per present array
+//   heightmaps      count u8, for every heightmap: kind u8, 256 heights u16
+//   block entities  byte length u32, bincode encoded BlockEntityStore
+//   biomes          256 biome ids u8, one per column
+//
+// Blocks are stored by name and state, so ids may change between versions of the block registry.
+//
+// Older versions are upgraded by io::fs::migration:
+//   1: palette entries were a u16 block table index followed by a u8 of visible faces
+//   2: no biomes
+
+use crate::core::*;
+use crate::model::biome::{Biome, BiomeMap};
+use crate::model::block::{BitRange, Block, BlockData};
+use crate::model::block_entity::BlockEntityStore;
+use crate::model::block_state::{BlockStateDescriptor, StateError};
+use crate::model::chunk::{Chunk, SubChunk};
+use crate::model::config;
+use crate::model::heightmap::{self, Heightmap, HeightmapKind};
+use crate::model::light::{LightArray, LightKind, LIGHT_ARRAY_BYTES};
+use crate::model::palette::{BitArray, PalettedStorage};
+use crate::model::registry::BlockRegistry;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+pub const MAGIC: &[u8; 4] = b"RCCH";
+pub const VERSION: u16 = 3;
+
+pub(crate) const SKY_LIGHT_FLAG: u8 = 1;
+pub(crate) const BLOCK_LIGHT_FLAG: u8 = 2;
+pub(crate) const HEADER_BYTES: usize = 6;
+
+const TABLE_INDEX_BITS: BitRange = BitRange{ begin: 0, size: 16 };
+const PALETTE_VISIBLE_BITS: BitRange = BitRange{ begin: 16, size: 6 };
+
+#[derive(Debug)]
+pub enum ChunkFormatError {
+    UnexpectedEnd,
+    BadMagic,
+    UnsupportedVersion(u16),
+    InvalidData(String),
+    UnknownBlock(BlockStateDescriptor, StateError),
+    BlockEntities(String),
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        return Reader{ data, offset: 0 };
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.offset..];
+        self.offset = self.data.len();
+        return rest;
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], ChunkFormatError> {
+        if self.data.len() - self.offset < len {
+            return Err(ChunkFormatError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        return Ok(bytes);
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ChunkFormatError> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ChunkFormatError> {
+        return Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()));
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ChunkFormatError> {
+        return Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()));
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, ChunkFormatError> {
+        return Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()));
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, ChunkFormatError> {
+        return Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()));
+    }
+
+    pub(crate) fn str(&mut self) -> Result<String, ChunkFormatError> {
+        let len = self.u16()? as usize;
+        return String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| ChunkFormatError::InvalidData("string is not UTF-8".to_string()));
+    }
+}
+
+pub(crate) fn invalid(what: &str) -> ChunkFormatError {
+    return ChunkFormatError::InvalidData(what.to_string());
+}
+
+pub(crate) fn palette_entry(table_index: BlockData, visible_mask: BlockData) -> BlockData {
+    return PALETTE_VISIBLE_BITS.set(TABLE_INDEX_BITS.set(0, table_index), visible_mask);
+}
+
+pub(crate) fn write_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+pub fn encode(chunk: &Chunk, registry: &BlockRegistry) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&chunk.pos().x.to_le_bytes());
+    out.extend_from_slice(&chunk.pos().y.to_le_bytes());
+
+    // Palette entries differing only in visibility share a table entry.
+    let mut table: Vec<BlockStateDescriptor> = Vec::new();
+    let mut table_index = HashMap::new();
+    let mut subchunks = Vec::new();
+    for (pos, subchunk) in chunk.allocated_subchunks() {
+        let mut out = Vec::new();
+        out.push(pos.z as u8);
+
+        let storage = subchunk.storage();
+        out.extend_from_slice(&(storage.palette().len() as u16).to_le_bytes());
+        for block in storage.palette() {
+            let mut state = *block;
+            state.set_visible_mask(0);
+            let idx = *table_index.entry(state.data()).or_insert_with(|| {
+                table.push(registry.describe(&state));
+                table.len() - 1
+            });
+            out.extend_from_slice(&palette_entry(idx as BlockData, block.visible_mask() as BlockData).to_le_bytes());
+        }
+
+        match storage.indices() {
+            Some(indices) => {
+                out.push(indices.bits());
+                out.extend_from_slice(&(indices.words().len() as u16).to_le_bytes());
+                for word in indices.words() {
+                    out.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            None => { out.push(0); }
+        }
+
+        let sky = subchunk.light(LightKind::Sky);
+        let block = subchunk.light(LightKind::Block);
+        let flags = if sky.is_some() { SKY_LIGHT_FLAG } else { 0 } | if block.is_some() { BLOCK_LIGHT_FLAG } else { 0 };
+        out.push(flags);
+        for light in sky.iter().chain(block.iter()) {
+            out.extend_from_slice(light.as_bytes());
+        }
+        subchunks.push(out);
+    }
+
+    out.extend_from_slice(&(table.len() as u16).to_le_bytes());
+    for descriptor in table.iter() {
+        write_str(&mut out, &descriptor.name);
+        out.push(descriptor.properties.len() as u8);
+        for (property, value) in descriptor.properties.iter() {
+            write_str(&mut out, property);
+            write_str(&mut out, value);
+        }
+    }
+    out.push(subchunks.len() as u8);
+    for subchunk in subchunks.iter() {
+        out.extend_from_slice(subchunk);
+    }
+
+    out.push(chunk.heightmaps().len() as u8);
+    for heightmap in chunk.heightmaps() {
+        out.push(heightmap.kind().index() as u8);
+        for height in heightmap.heights() {
+            out.extend_from_slice(&height.to_le_bytes());
+        }
+    }
+
+    let block_entities = bincode::serialize(chunk.block_entities()).expect("block entities are always serializable");
+    out.extend_from_slice(&(block_entities.len() as u32).to_le_bytes());
+    out.extend_from_slice(&block_entities);
+
+    for biome in chunk.biomes().biomes() {
+        out.push(biome.id());
+    }
+    return out;
+}
+
+// Reads only the header, returns the format version.
+pub fn read_version(data: &[u8]) -> Result<u16, ChunkFormatError> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(ChunkFormatError::BadMagic);
+    }
+    return reader.u16();
+}
+
+pub fn decode(data: &[u8], registry: &BlockRegistry) -> Result<Chunk, ChunkFormatError> {
+    let version = read_version(data)?;
+    if version != VERSION {
+        return Err(ChunkFormatError::UnsupportedVersion(version));
+    }
+    let mut reader = Reader{ data, offset: HEADER_BYTES };
+
+    let pos = ChunkPos::new(reader.i32()? as PosScalar, reader.i32()? as PosScalar);
+    let mut chunk = Chunk::empty(pos);
+
+    let table_len = reader.u16()? as usize;
+    let mut table = Vec::with_capacity(table_len);
+    for _ in 0..table_len {
+        let name = reader.str()?;
+        let property_count = reader.u8()?;
+        let mut descriptor = BlockStateDescriptor{ name, properties: Default::default() };
+        for _ in 0..property_count {
+            let property = reader.str()?;
+            descriptor.properties.insert(property, reader.str()?);
+        }
+        let block = registry.resolve(&descriptor).map_err(|e| ChunkFormatError::UnknownBlock(descriptor, e))?;
+        table.push(block);
+    }
+
+    let subchunk_count = reader.u8()?;
+    for _ in 0..subchunk_count {
+        let subchunk_pos = pos.subchunk(reader.u8()? as PosScalar);
+        if chunk.subchunk(subchunk_pos).is_some() || !chunk.contains(subchunk_pos.origin()) {
+            return Err(invalid("bad sub-chunk index"));
+        }
+
+        let palette_len = reader.u16()? as usize;
+        let mut palette = Vec::with_capacity(palette_len);
+        for _ in 0..palette_len {
+            let entry = reader.u32()?;
+            let (index, mask) = (TABLE_INDEX_BITS.get(entry), PALETTE_VISIBLE_BITS.get(entry));
+            if palette_entry(index, mask) != entry {
+                return Err(invalid("bad palette entry"));
+            }
+            let mut block: Block = *table.get(index as usize).ok_or_else(|| invalid("bad block table index"))?;
+            block.set_visible_mask(mask as u8);
+            palette.push(block);
+        }
+
+        let indices = match reader.u8()? {
+            0 => None,
+            bits => {
+                let word_count = reader.u16()? as usize;
+                let mut words = Vec::with_capacity(word_count);
+                for _ in 0..word_count {
+                    words.push(reader.u64()?);
+                }
+                Some(BitArray::from_words(bits, LocalPos::VOLUME, words).ok_or_else(|| invalid("bad index array"))?)
+            }
+        };
+        let storage = PalettedStorage::from_parts(palette, indices, LocalPos::VOLUME)
+            .ok_or_else(|| invalid("inconsistent palette"))?;
+        let mut subchunk = SubChunk::from_storage(storage).unwrap();
+
+        let flags = reader.u8()?;
+        if flags & !(SKY_LIGHT_FLAG | BLOCK_LIGHT_FLAG) != 0 {
+            return Err(invalid("unknown light flags"));
+        }
+        for (flag, kind) in [(SKY_LIGHT_FLAG, LightKind::Sky), (BLOCK_LIGHT_FLAG, LightKind::Block)].iter() {
+            if flags & flag != 0 {
+                subchunk.set_light(*kind, LightArray::from_bytes(reader.bytes(LIGHT_ARRAY_BYTES)?));
+            }
+        }
+        subchunk.clear_dirty();
+        chunk.set_subchunk(subchunk_pos, subchunk);
+    }
+
+    let heightmap_count = reader.u8()?;
+    let mut heightmaps = Vec::with_capacity(heightmap_count as usize);
+    for _ in 0..heightmap_count {
+        let kind = *HeightmapKind::ALL.get(reader.u8()? as usize).ok_or_else(|| invalid("unknown heightmap kind"))?;
+        let mut heights = Vec::with_capacity(heightmap::COLUMNS);
+        for _ in 0..heightmap::COLUMNS {
+            let height = reader.u16()?;
+            if height as u32 > config::BUILD_LIMIT * config::SUBCHUNK_SIZE {
+                return Err(invalid("height out of range"));
+            }
+            heights.push(height);
+        }
+        heightmaps.push(Heightmap::from_heights(kind, heights).unwrap());
+    }
+    chunk.set_heightmaps(heightmaps, registry);
+
+    let block_entities_len = reader.u32()? as usize;
+    let mut block_entities: BlockEntityStore = bincode::deserialize(reader.bytes(block_entities_len)?)
+        .map_err(|e| ChunkFormatError::BlockEntities(e.to_string()))?;
+    if block_entities.items_mut().any(|stack| stack.count == 0) {
+        return Err(ChunkFormatError::BlockEntities("empty item stack".to_string()));
+    }
+    chunk.set_block_entities(block_entities);
+
+    let mut biomes = Vec::with_capacity(heightmap::COLUMNS);
+    for _ in 0..heightmap::COLUMNS {
+        biomes.push(Biome::from_id(reader.u8()?).ok_or_else(|| invalid("unknown biome"))?);
+    }
+    chunk.set_biomes(BiomeMap::from_biomes(biomes).unwrap());
+
+    if reader.offset != data.len() {
+        return Err(invalid("trailing data"));
+    }
+    chunk.clear_dirty();
+    return Ok(chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block_entity::{BlockEntity, ItemStack};
+    use nalgebra::Vector3;
+
+    fn sample_chunk(registry: &BlockRegistry) -> Chunk {
+        let mut chunk = Chunk::empty(ChunkPos::new(-3, 7));
+        let origin = chunk.pos().origin();
+        let lever = registry.block("lever").unwrap().with(registry, "powered", "true").unwrap();
+        chunk.update_blocks_in(chunk.pos().column(0, 3), registry, |pos, _| {
+            return if pos.z < 3 { registry.block("stone") } else { registry.block("grass_block") };
+        });
+        chunk.set_block(origin + Vector3::new(4, 4, 4), lever, registry);
+        chunk.set_block(origin + Vector3::new(1, 2, 70), registry.block("glass").unwrap(), registry);
+
+        let chest_pos = origin + Vector3::new(8, 8, 4);
+        chunk.set_block(chest_pos, registry.block("chest").unwrap(), registry);
+        if let Some(BlockEntity::Chest(chest)) = chunk.block_entity_mut(chest_pos) {
+            chest.slots[3] = Some(ItemStack::new("dirt", 12));
+        }
+
+        let subchunk_pos = chunk.pos().subchunk(0);
+        let mut light = LightArray::new(0);
+        light.set(LocalPos::new(1, 2, 3), 14);
+        chunk.subchunk_mut(subchunk_pos).unwrap().set_light(LightKind::Block, Some(light));
+        chunk.subchunk_mut(subchunk_pos).unwrap().set_light(LightKind::Sky, Some(LightArray::new(15)));
+        chunk.set_biome(origin.x + 15, origin.y + 3, Biome::Swamp);
+        return chunk;
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let registry = BlockRegistry::builtin();
+        let chunk = sample_chunk(&registry);
+        let data = encode(&chunk, &registry);
+        assert_eq!(read_version(&data).unwrap(), VERSION);
+
+        let decoded = decode(&data, &registry).unwrap();
+        assert_eq!(decoded.pos(), chunk.pos());
+        assert!(!decoded.is_dirty());
+        assert!(decoded.blocks().eq(chunk.blocks()));
+        assert_eq!(decoded.heightmaps(), chunk.heightmaps());
+        assert_eq!(decoded.block_entities(), chunk.block_entities());
+        assert_eq!(decoded.biomes(), chunk.biomes());
+        assert_eq!(decoded.biome(chunk.pos().origin().x + 15, chunk.pos().origin().y + 3), Some(Biome::Swamp));
+        assert_eq!(decoded.allocated_subchunks().count(), 2);
+
+        let subchunk = decoded.subchunk(chunk.pos().subchunk(0)).unwrap();
+        assert_eq!(subchunk.light_level(LightKind::Block, LocalPos::new(1, 2, 3)), 14);
+        assert_eq!(subchunk.light_level(LightKind::Sky, LocalPos::new(1, 2, 3)), 15);
+        assert!(decoded.subchunk(chunk.pos().subchunk(4)).unwrap().light(LightKind::Sky).is_none());
+
+        // Encoding is deterministic.
+        assert_eq!(encode(&decoded, &registry), data);
+    }
+
+    #[test]
+    fn corrupt_data_is_rejected() {
+        let registry = BlockRegistry::builtin();
+        let data = encode(&sample_chunk(&registry), &registry);
+
+        for len in 0..data.len() {
+            assert!(decode(&data[..len], &registry).is_err(), "truncated to {} bytes", len);
+        }
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(decode(&bad_magic, &registry), Err(ChunkFormatError::BadMagic)));
+
+        let mut future = data.clone();
+        future[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(decode(&future, &registry), Err(ChunkFormatError::UnsupportedVersion(_))));
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(matches!(decode(&trailing, &registry), Err(ChunkFormatError::InvalidData(_))));
+
+        // First height of the first heightmap, right before the block entities and biomes
+        let heightmaps = data.len() - heightmap::COLUMNS - bincode::serialize(sample_chunk(&registry).block_entities()).unwrap().len() - 4
+            - HeightmapKind::ALL.len() * (1 + heightmap::COLUMNS * 2);
+        assert_eq!(data[heightmaps - 1] as usize, HeightmapKind::ALL.len());
+        let mut too_high = data.clone();
+        too_high[heightmaps + 1..heightmaps + 3].copy_from_slice(&(config::BUILD_LIMIT as u16 * config::SUBCHUNK_SIZE as u16 + 1).to_le_bytes());
+        assert!(matches!(decode(&too_high, &registry), Err(ChunkFormatError::InvalidData(_))));
+
+        let mut empty_stack = sample_chunk(&registry);
+        if let Some(BlockEntity::Chest(chest)) = empty_stack.block_entity_mut(empty_stack.pos().origin() + Vector3::new(8, 8, 4)) {
+            chest.slots[3].as_mut().unwrap().count = 0;
+        }
+        assert!(matches!(decode(&encode(&empty_stack, &registry), &registry), Err(ChunkFormatError::BlockEntities(_))));
+
+        let mut unknown_biome = data.clone();
+        *unknown_biome.last_mut().unwrap() = 200;
+        assert!(matches!(decode(&unknown_biome, &registry), Err(ChunkFormatError::InvalidData(_))));
+
+        // Rename "stone" in the block table to something unknown.
+        let mut unknown = data.clone();
+        let name_offset = unknown.windows(5).position(|w| w == b"stone").unwrap();
+        unknown[name_offset..name_offset + 5].copy_from_slice(b"stane");
+        assert!(matches!(decode(&unknown, &registry), Err(ChunkFormatError::UnknownBlock(_, _))));
+
+        // Flipping bytes must never panic.
+        for offset in 0..data.len() {
+            let mut flipped = data.clone();
+            flipped[offset] ^= 0xff;
+            let _ = decode(&flipped, &registry);
+        }
+    }
+}
@@ -0,0 +1,440 @@
+// Upgrades worlds written by older versions of the engine, one version at a time.
+//
+// Level (model::level::LEVEL_FORMAT_VERSION), upgraded when the world is loaded:
+//   1 -> 2   every region file is rewritten with chunk checksums and the backup header sector
+//   2 -> 3   last_played is added, 0 for worlds that were never played since
+//
+// Chunks (chunk_format::VERSION), upgraded whenever they are read and written in the current
+// version the next time they are saved:
+//   1 -> 2   palette entries are packed into a u32, "grass" is renamed to "grass_block" in
+//            blocks and the items of block entities
+//   2 -> 3   biomes are added, every column gets Biome::DEFAULT
+//
+// Supporting a new version means bumping the constant, adding a step below and a fixture world
+// written by the previous version to fixtures/worlds, through fixtures/worlds/generate.sh.
+
+use crate::core::*;
+use crate::io::fs::atomic;
+use crate::io::fs::chunk_format::{self, ChunkFormatError, Reader};
+use crate::io::fs::region::{RegionError, RegionPos, RegionStore, REGION_SIZE, SECTOR_SIZE};
+use crate::model::biome::Biome;
+use crate::model::block_entity::BlockEntityStore;
+use crate::model::heightmap;
+use crate::model::level::{GeneratorSettings, LevelData, LEVEL_FORMAT_VERSION};
+use crate::model::light::LIGHT_ARRAY_BYTES;
+use crate::model::world::{LEVEL_BACKUP_FILE, LEVEL_FILE, REGION_DIR};
+
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io::{self, Read};
+use std::path::Path;
+
+// Region files of level version 1 before, during and after the upgrade to version 2
+const REGION_V1_DIR: &str = "region.v1";
+const REGION_V2_DIR: &str = "region.v2";
+
+// Blocks renamed by a chunk format version, applied to the blocks and items of chunks written by an older one.
+const BLOCK_RENAMES: &[(u16, &str, &str)] = &[
+    (2, "grass", "grass_block"),
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Io(io::Error),
+    Level(String),
+    UnsupportedVersion(u32),
+    Region(RegionError),
+}
+
+impl From<io::Error> for MigrationError {
+    fn from(error: io::Error) -> Self {
+        return MigrationError::Io(error);
+    }
+}
+
+impl From<RegionError> for MigrationError {
+    fn from(error: RegionError) -> Self {
+        return MigrationError::Region(error);
+    }
+}
+
+// Upgrades encoded chunk data to chunk_format::VERSION, data that is already current is returned as is.
+pub fn migrate_chunk(data: Vec<u8>) -> Result<Vec<u8>, ChunkFormatError> {
+    let mut data = data;
+    loop {
+        data = match chunk_format::read_version(&data)? {
+            chunk_format::VERSION => { return Ok(data); }
+            1 => chunk_v1_to_v2(&data)?,
+            2 => chunk_v2_to_v3(&data)?,
+            other => { return Err(ChunkFormatError::UnsupportedVersion(other)); }
+        };
+    }
+}
+
+fn renamed_block(name: String, version: u16) -> String {
+    return match BLOCK_RENAMES.iter().find(|(v, from, _)| *v == version && *from == name) {
+        Some((_, _, to)) => to.to_string(),
+        None => name,
+    };
+}
+
+fn chunk_v1_to_v2(data: &[u8]) -> Result<Vec<u8>, ChunkFormatError> {
+    let mut reader = Reader::new(data);
+    reader.bytes(chunk_format::HEADER_BYTES)?;
+    let mut out = Vec::with_capacity(data.len() * 2);
+    out.extend_from_slice(chunk_format::MAGIC);
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(reader.bytes(8)?);
+
+    let table_len = reader.u16()?;
+    out.extend_from_slice(&table_len.to_le_bytes());
+    for _ in 0..table_len {
+        chunk_format::write_str(&mut out, &renamed_block(reader.str()?, 2));
+        let property_count = reader.u8()?;
+        out.push(property_count);
+        for _ in 0..property_count as usize * 2 {
+            chunk_format::write_str(&mut out, &reader.str()?);
+        }
+    }
+
+    let subchunk_count = reader.u8()?;
+    out.push(subchunk_count);
+    for _ in 0..subchunk_count {
+        out.push(reader.u8()?);
+        let palette_len = reader.u16()?;
+        out.extend_from_slice(&palette_len.to_le_bytes());
+        for _ in 0..palette_len {
+            let table_index = reader.u16()?;
+            let visible = reader.u8()?;
+            if visible >= 1 << 6 {
+                return Err(chunk_format::invalid("visible faces"));
+            }
+            out.extend_from_slice(&chunk_format::palette_entry(table_index as u32, visible as u32).to_le_bytes());
+        }
+        let bits = reader.u8()?;
+        out.push(bits);
+        if bits != 0 {
+            let word_count = reader.u16()?;
+            out.extend_from_slice(&word_count.to_le_bytes());
+            out.extend_from_slice(reader.bytes(word_count as usize * 8)?);
+        }
+        let light_flags = reader.u8()?;
+        out.push(light_flags);
+        let arrays = (light_flags & chunk_format::SKY_LIGHT_FLAG != 0) as usize
+            + (light_flags & chunk_format::BLOCK_LIGHT_FLAG != 0) as usize;
+        out.extend_from_slice(reader.bytes(arrays * LIGHT_ARRAY_BYTES)?);
+    }
+
+    // Heightmaps didn't change
+    let heightmap_count = reader.u8()?;
+    out.push(heightmap_count);
+    out.extend_from_slice(reader.bytes(heightmap_count as usize * (1 + heightmap::COLUMNS * 2))?);
+
+    let len = reader.u32()? as usize;
+    let mut block_entities: BlockEntityStore = bincode::deserialize(reader.bytes(len)?)
+        .map_err(|e| ChunkFormatError::BlockEntities(e.to_string()))?;
+    for stack in block_entities.items_mut() {
+        stack.item = renamed_block(std::mem::take(&mut stack.item), 2);
+    }
+    let block_entities = bincode::serialize(&block_entities).expect("block entities are always serializable");
+    out.extend_from_slice(&(block_entities.len() as u32).to_le_bytes());
+    out.extend_from_slice(&block_entities);
+    out.extend_from_slice(reader.rest());
+    return Ok(out);
+}
+
+// Biomes come last, nothing else moves.
+fn chunk_v2_to_v3(data: &[u8]) -> Result<Vec<u8>, ChunkFormatError> {
+    let mut reader = Reader::new(data);
+    reader.bytes(chunk_format::HEADER_BYTES)?;
+    let mut out = Vec::with_capacity(data.len() + heightmap::COLUMNS);
+    out.extend_from_slice(chunk_format::MAGIC);
+    out.extend_from_slice(&3u16.to_le_bytes());
+    out.extend_from_slice(reader.rest());
+    out.extend(std::iter::repeat_n(Biome::DEFAULT.id(), heightmap::COLUMNS));
+    return Ok(out);
+}
+
+#[derive(Deserialize)]
+struct VersionHeader {
+    format_version: u32,
+}
+
+// level.ron as written by level versions 1 and 2
+#[derive(Serialize, Deserialize)]
+struct LevelDataV2 {
+    format_version: u32,
+    seed: u64,
+    spawn: BlockPos,
+    time: u64,
+    generator: GeneratorSettings,
+}
+
+fn parse<'a, T: Deserialize<'a>>(source: &'a str) -> Result<T, MigrationError> {
+    return ron::de::from_str(source).map_err(|e| MigrationError::Level(e.to_string()));
+}
+
+// Parses the contents of a level file of the world in world_dir, upgrading the world on disk if needed.
+// Steps that rewrite files persist the level right after, so a crash never repeats them on upgraded data.
+pub fn migrate_level(world_dir: &Path, source: &str) -> Result<LevelData, MigrationError> {
+    let version = parse::<VersionHeader>(source)?.format_version;
+    if version == LEVEL_FORMAT_VERSION {
+        return parse(source);
+    }
+    if version == 0 || version > LEVEL_FORMAT_VERSION {
+        return Err(MigrationError::UnsupportedVersion(version));
+    }
+
+    let mut level: LevelDataV2 = parse(source)?;
+    if level.format_version == 1 {
+        upgrade_regions_v1(world_dir)?;
+        level.format_version = 2;
+        let data = ron::ser::to_string_pretty(&level, ron::ser::PrettyConfig::default())
+            .map_err(|e| MigrationError::Level(e.to_string()))?;
+        atomic::write_atomic(&world_dir.join(LEVEL_FILE), data.as_bytes())?;
+        // The backup refers to the old region files
+        let backup = world_dir.join(LEVEL_BACKUP_FILE);
+        if backup.exists() {
+            std::fs::remove_file(backup)?;
+        }
+        std::fs::remove_dir_all(world_dir.join(REGION_V1_DIR))?;
+    }
+
+    return Ok(LevelData{
+        format_version: LEVEL_FORMAT_VERSION,
+        seed: level.seed,
+        spawn: level.spawn,
+        time: level.time,
+        generator: level.generator,
+        last_played: 0,
+    });
+}
+
+// The new files are built next to the old ones, which are moved to REGION_V1_DIR before the swap.
+// If that dir exists a previous attempt was interrupted and it holds the only complete copy.
+fn upgrade_regions_v1(world_dir: &Path) -> Result<(), MigrationError> {
+    let region_dir = world_dir.join(REGION_DIR);
+    let old_dir = world_dir.join(REGION_V1_DIR);
+    let new_dir = world_dir.join(REGION_V2_DIR);
+    let source = if old_dir.is_dir() { old_dir.clone() } else { region_dir.clone() };
+
+    if new_dir.exists() {
+        std::fs::remove_dir_all(&new_dir)?;
+    }
+    let mut store = RegionStore::new(&new_dir)?;
+    if source.is_dir() {
+        for entry in std::fs::read_dir(&source)? {
+            let path = entry?.path();
+            let pos = match path.file_name().and_then(|n| n.to_str()).and_then(RegionPos::from_file_name) {
+                Some(pos) => pos,
+                None => { continue; }
+            };
+            for (chunk_pos, data) in read_region_v1(&path, pos)? {
+                store.write_chunk(chunk_pos, &data)?;
+            }
+        }
+    }
+    store.sync()?;
+
+    if source == region_dir {
+        if region_dir.exists() {
+            std::fs::rename(&region_dir, &old_dir)?;
+        } else {
+            std::fs::create_dir_all(&old_dir)?;
+        }
+    } else if region_dir.exists() {
+        std::fs::remove_dir_all(&region_dir)?;
+    }
+    std::fs::rename(&new_dir, &region_dir)?;
+    atomic::sync_dir(world_dir)?;
+    return Ok(());
+}
+
+// Region files of level version 1: two header sectors (locations, timestamps) and chunk entries
+// made of length u32, compression u8 and the payload, without checksums or backups.
+fn read_region_v1(path: &Path, pos: RegionPos) -> Result<Vec<(ChunkPos, Vec<u8>)>, MigrationError> {
+    let corrupt = |what: String| MigrationError::Region(RegionError::Corrupt(format!("{}: {}", path.display(), what)));
+    let data = std::fs::read(path)?;
+    if data.len() < 2 * SECTOR_SIZE {
+        return Err(corrupt("missing header".to_string()));
+    }
+
+    let mut chunks = Vec::new();
+    let origin = pos.origin();
+    for idx in 0..(REGION_SIZE * REGION_SIZE) as usize {
+        let raw = u32::from_le_bytes(data[idx * 4..idx * 4 + 4].try_into().unwrap());
+        if raw == 0 {
+            continue;
+        }
+        let chunk_pos = ChunkPos::new(origin.x + idx as PosScalar % REGION_SIZE, origin.y + idx as PosScalar / REGION_SIZE);
+        let start = (raw >> 8) as usize * SECTOR_SIZE;
+        let end = start + (raw & 0xff) as usize * SECTOR_SIZE;
+        if start < 2 * SECTOR_SIZE || end > data.len() || end - start < 5 {
+            return Err(corrupt(format!("bad location of chunk {:?}", chunk_pos)));
+        }
+        let entry = &data[start..end];
+        let len = u32::from_le_bytes(entry[..4].try_into().unwrap()) as usize;
+        if len == 0 || len > entry.len() - 4 {
+            return Err(corrupt(format!("bad length of chunk {:?}", chunk_pos)));
+        }
+        let payload = &entry[5..4 + len];
+        let chunk = match entry[4] {
+            0 => payload.to_vec(),
+            1 => {
+                let mut decompressed = Vec::new();
+                ZlibDecoder::new(payload).read_to_end(&mut decompressed)
+                    .map_err(|e| corrupt(format!("chunk {:?}: {}", chunk_pos, e)))?;
+                decompressed
+            }
+            other => { return Err(corrupt(format!("unknown compression {} of chunk {:?}", other, chunk_pos))); }
+        };
+        chunks.push((chunk_pos, chunk));
+    }
+    return Ok(chunks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block_entity::{BlockEntity, ItemStack};
+    use crate::model::ticket::{Ticket, TicketKind};
+    use crate::model::world::World;
+    use std::path::PathBuf;
+
+    fn fixture(version: &str) -> PathBuf {
+        return Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join("worlds").join(version);
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                std::fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    fn temp_copy(version: &str, name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustcraft-{}-{}-{}", name, version, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        copy_dir(&fixture(version), &dir);
+        return dir;
+    }
+
+    fn load_fixture_world(dir: &Path) -> World {
+        let mut world = World::load(dir).unwrap();
+        world.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 1));
+        world.add_ticket(Ticket::new(TicketKind::Plugin, BlockPos::new(200, -100, 0).chunk(), 0));
+        world.wait_for_chunks().unwrap();
+        return world;
+    }
+
+    // Contents every fixture world was created with
+    fn check_fixture_world(world: &World) {
+        let registry = world.registry();
+        assert_eq!(world.level().seed, 42);
+        assert_eq!(world.level().spawn, BlockPos::new(0, 0, 11));
+        assert_eq!(world.level().time, 12345);
+        assert_eq!(world.level().format_version, LEVEL_FORMAT_VERSION);
+
+        let grass = registry.block("grass_block").unwrap();
+        let dirt = registry.block("dirt").unwrap();
+        let stone = registry.block("stone").unwrap();
+        for pos in BlockBox::new(BlockPos::new(-3, -3, 0), BlockPos::new(3, 3, 10)).positions() {
+            let expected = match pos.z { 10 => grass, 7..=9 => dirt, _ => stone };
+            assert_eq!(world.get_block(pos).unwrap().block_id(), expected.block_id(), "{:?}", pos);
+        }
+        assert_eq!(world.get_block(BlockPos::new(200, -100, 40)).unwrap().block_id(), grass.block_id());
+
+        let lever = registry.describe(world.get_block(BlockPos::new(2, 2, 11)).unwrap());
+        assert_eq!(lever.name, "lever");
+        assert_eq!(lever.properties["facing"], "west");
+        assert_eq!(lever.properties["powered"], "true");
+
+        let chest = BlockPos::new(-3, -3, 11);
+        match world.map().loaded_chunk(chest.chunk()).unwrap().block_entity(chest) {
+            Some(BlockEntity::Chest(data)) => assert_eq!(data.slots[0], Some(ItemStack::new("grass_block", 5))),
+            other => panic!("expected a chest, got {:?}", other),
+        }
+        assert_eq!(world.player("alice").unwrap().health, 12.0);
+        assert_eq!(world.map().loaded_chunk(ChunkPos::new(0, 0)).unwrap().biome(0, 0), Some(Biome::DEFAULT));
+    }
+
+    #[test]
+    fn fixture_worlds_are_upgraded() {
+        for version in &["v1", "v2", "v3"] {
+            let dir = temp_copy(version, "migration");
+            let mut world = load_fixture_world(&dir);
+            check_fixture_world(&world);
+            assert_eq!(world.level().last_played, 0);
+            assert!(!dir.join(REGION_V1_DIR).exists());
+            assert!(!dir.join(REGION_V2_DIR).exists());
+
+            // Loading again finds the upgraded level and region files
+            check_fixture_world(&load_fixture_world(&dir));
+            world.save(&dir).unwrap();
+            let reloaded = load_fixture_world(&dir);
+            check_fixture_world(&reloaded);
+            assert!(reloaded.level().last_played > 0);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn interrupted_region_upgrade_is_resumed() {
+        let dir = temp_copy("v1", "migration-interrupted");
+        // Crashed after moving the old files away, with a partial set of new ones
+        std::fs::rename(dir.join(REGION_DIR), dir.join(REGION_V1_DIR)).unwrap();
+        std::fs::create_dir_all(dir.join(REGION_V2_DIR)).unwrap();
+        std::fs::write(dir.join(REGION_V2_DIR).join("r.0.0.rcr"), vec![0; 100]).unwrap();
+        check_fixture_world(&load_fixture_world(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_are_upgraded() {
+        let dir = temp_copy("v2", "migration-chunks");
+        let mut store = RegionStore::new(&dir.join(REGION_DIR)).unwrap();
+        let data = store.read_chunk(ChunkPos::new(0, 0)).unwrap().unwrap();
+        assert_eq!(chunk_format::read_version(&data).unwrap(), 1);
+        let upgraded = migrate_chunk(data.clone()).unwrap();
+        assert_eq!(chunk_format::read_version(&upgraded).unwrap(), chunk_format::VERSION);
+        assert_eq!(migrate_chunk(upgraded.clone()).unwrap(), upgraded);
+
+        let registry = crate::model::registry::BlockRegistry::builtin();
+        let chunk = chunk_format::decode(&upgraded, &registry).unwrap();
+        assert_eq!(chunk.block(BlockPos::new(0, 0, 10)).unwrap().block_id(), registry.block("grass_block").unwrap().block_id());
+        assert!(chunk.biomes().biomes().iter().all(|b| *b == Biome::DEFAULT));
+
+        for len in 0..data.len() {
+            assert!(migrate_chunk(data[..len].to_vec()).and_then(|d| chunk_format::decode(&d, &registry)).is_err());
+        }
+        // Chunks the previous version wrote only miss the biomes
+        let v3_dir = temp_copy("v3", "migration-chunks");
+        let data = RegionStore::new(&v3_dir.join(REGION_DIR)).unwrap().read_chunk(ChunkPos::new(0, 0)).unwrap().unwrap();
+        assert_eq!(chunk_format::read_version(&data).unwrap(), 2);
+        let chunk = chunk_format::decode(&migrate_chunk(data.clone()).unwrap(), &registry).unwrap();
+        assert_eq!(chunk.biome(15, 15), Some(Biome::DEFAULT));
+        assert!(migrate_chunk(data[..chunk_format::HEADER_BYTES - 1].to_vec()).is_err());
+        std::fs::remove_dir_all(&v3_dir).unwrap();
+
+        let mut future = upgraded;
+        future[4..6].copy_from_slice(&(chunk_format::VERSION + 1).to_le_bytes());
+        assert!(matches!(migrate_chunk(future), Err(ChunkFormatError::UnsupportedVersion(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_level_versions_are_rejected() {
+        let dir = std::env::temp_dir();
+        assert!(matches!(migrate_level(&dir, "(format_version: 0)"), Err(MigrationError::UnsupportedVersion(0))));
+        let future = format!("(format_version: {})", LEVEL_FORMAT_VERSION + 1);
+        assert!(matches!(migrate_level(&dir, &future), Err(MigrationError::UnsupportedVersion(_))));
+        assert!(matches!(migrate_level(&dir, "(seed: 1)"), Err(MigrationError::Level(_))));
+    }
+}
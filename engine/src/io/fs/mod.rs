@@ -0,0 +1,4 @@
+pub mod atomic;
+pub mod chunk_format;
+pub mod migration;
+pub mod region;
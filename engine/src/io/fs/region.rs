@@ -0,0 +1,603 @@
+// Region files group REGION_SIZE x REGION_SIZE chunks in a single file made of 4 KiB sectors.
+//
+//   sector 0     location of every chunk: u32 per chunk, first sector << 8 | sector count, 0 if missing
+//   sector 1     last write time of every chunk: u32 seconds since the unix epoch
+//   sector 2     location of the previous copy of every chunk, same encoding as sector 0
+//   sector 3..   chunk data: length u32 (of what follows), compression u8, crc32 of the payload u32, payload
+//
+// Header entries are little endian and ordered by local x + local y * REGION_SIZE.
+//
+// Chunks are never overwritten in place. A write goes to the first run of free sectors that is large
+// enough (appending to the file only when there is none) and the header is only updated by flush,
+// after the data reached the disk. A crash before that leaves the header pointing to the old data.
+// The previous copy of each chunk is kept until the next write, reads fall back to it when the
+// checksum of the current copy doesn't match.
+
+use crate::core::*;
+use crate::io::fs::atomic;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const REGION_SIZE: PosScalar = 32;
+pub const SECTOR_SIZE: usize = 4096;
+
+const CHUNKS_PER_REGION: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_SECTORS: usize = 3;
+const MAX_SECTORS_PER_CHUNK: usize = 255;
+const CHUNK_HEADER_BYTES: usize = 9;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZLIB: u8 = 1;
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(io::Error),
+    Corrupt(String),
+    // Compressed size of a chunk that doesn't fit in MAX_SECTORS_PER_CHUNK sectors
+    ChunkTooLarge(usize),
+}
+
+impl From<io::Error> for RegionError {
+    fn from(error: io::Error) -> Self {
+        return RegionError::Io(error);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RegionPos {
+    pub x: PosScalar,
+    pub y: PosScalar,
+}
+
+impl RegionPos {
+    pub fn of(chunk: ChunkPos) -> Self {
+        return RegionPos{ x: chunk.x.div_euclid(REGION_SIZE), y: chunk.y.div_euclid(REGION_SIZE) };
+    }
+
+    pub fn file_name(&self) -> String {
+        return format!("r.{}.{}.rcr", self.x, self.y);
+    }
+
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let mut parts = name.strip_prefix("r.")?.strip_suffix(".rcr")?.split('.');
+        let x = parts.next()?.parse().ok()?;
+        let y = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        return Some(RegionPos{ x, y });
+    }
+
+    pub fn origin(&self) -> ChunkPos {
+        return ChunkPos::new(self.x * REGION_SIZE, self.y * REGION_SIZE);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Location {
+    sector: usize,
+    count: usize,
+}
+
+impl Location {
+    fn from_raw(raw: u32) -> Option<Self> {
+        if raw == 0 {
+            return None;
+        }
+        return Some(Location{ sector: (raw >> 8) as usize, count: (raw & 0xff) as usize });
+    }
+
+    fn raw(&self) -> u32 {
+        return (self.sector as u32) << 8 | self.count as u32;
+    }
+}
+
+fn chunk_index(pos: ChunkPos) -> usize {
+    return (pos.x.rem_euclid(REGION_SIZE) + pos.y.rem_euclid(REGION_SIZE) * REGION_SIZE) as usize;
+}
+
+fn now() -> u32 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32);
+}
+
+pub struct Region {
+    pos: RegionPos,
+    file: File,
+    locations: Vec<Option<Location>>,
+    timestamps: Vec<u32>,
+    backups: Vec<Option<Location>>,
+    // One entry per sector of the file, header sectors included
+    used_sectors: Vec<bool>,
+    // Header entries changed since the last flush
+    pending: BTreeSet<usize>,
+    // Sectors the header on disk may still point to, freed by flush
+    pending_release: Vec<Location>,
+    recovered: Vec<ChunkPos>,
+}
+
+impl Region {
+    // Creates the file if it doesn't exist yet.
+    pub fn open(path: &Path, pos: RegionPos) -> Result<Self, RegionError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut len = file.metadata()?.len() as usize;
+        if len == 0 {
+            file.write_all(&[0; HEADER_SECTORS * SECTOR_SIZE])?;
+        } else if len < HEADER_SECTORS * SECTOR_SIZE {
+            return Err(RegionError::Corrupt(format!("file size {} is smaller than the header", len)));
+        } else if !len.is_multiple_of(SECTOR_SIZE) {
+            // Left by a write that was interrupted before the header pointed to it, nothing refers to it.
+            len -= len % SECTOR_SIZE;
+            file.set_len(len as u64)?;
+        }
+
+        let mut header = vec![0; HEADER_SECTORS * SECTOR_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        let entry = |idx: usize| u32::from_le_bytes(header[idx * 4..idx * 4 + 4].try_into().unwrap());
+
+        let sector_count = std::cmp::max(len, header.len()) / SECTOR_SIZE;
+        let mut used_sectors = vec![false; sector_count];
+        used_sectors[..HEADER_SECTORS].iter_mut().for_each(|s| *s = true);
+        let mut locations = Vec::with_capacity(CHUNKS_PER_REGION);
+        for idx in 0..CHUNKS_PER_REGION {
+            let location = Location::from_raw(entry(idx));
+            if let Some(location) = location {
+                if !Region::claim(&mut used_sectors, location) {
+                    return Err(RegionError::Corrupt(format!("bad location of chunk {}", idx)));
+                }
+            }
+            locations.push(location);
+        }
+        // A broken backup entry only loses the backup.
+        let backups = (0..CHUNKS_PER_REGION)
+            .map(|idx| Location::from_raw(entry(2 * CHUNKS_PER_REGION + idx)).filter(|l| Region::claim(&mut used_sectors, *l)))
+            .collect();
+        let timestamps = (0..CHUNKS_PER_REGION).map(|idx| entry(CHUNKS_PER_REGION + idx)).collect();
+
+        return Ok(Region{
+            pos, file, locations, timestamps, backups, used_sectors,
+            pending: BTreeSet::new(),
+            pending_release: Vec::new(),
+            recovered: Vec::new(),
+        });
+    }
+
+    // Marks the sectors as used, fails if they are outside of the file or already used.
+    fn claim(used_sectors: &mut [bool], location: Location) -> bool {
+        let end = location.sector + location.count;
+        if location.sector < HEADER_SECTORS || location.count == 0 || end > used_sectors.len() {
+            return false;
+        }
+        if used_sectors[location.sector..end].iter().any(|used| *used) {
+            return false;
+        }
+        used_sectors[location.sector..end].iter_mut().for_each(|used| *used = true);
+        return true;
+    }
+
+    pub fn pos(&self) -> RegionPos {
+        return self.pos;
+    }
+
+    fn index(&self, pos: ChunkPos) -> usize {
+        assert_eq!(RegionPos::of(pos), self.pos, "chunk {:?} is not in region {:?}", pos, self.pos);
+        return chunk_index(pos);
+    }
+
+    pub fn contains_chunk(&self, pos: ChunkPos) -> bool {
+        return self.locations[self.index(pos)].is_some();
+    }
+
+    // Seconds since the unix epoch of the last write, None for missing chunks.
+    pub fn timestamp(&self, pos: ChunkPos) -> Option<u32> {
+        let idx = self.index(pos);
+        return self.locations[idx].map(|_| self.timestamps[idx]);
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        let origin = self.pos.origin();
+        return self.locations.iter().enumerate().filter(|(_, l)| l.is_some()).map(move |(idx, _)| {
+            return ChunkPos::new(origin.x + idx as PosScalar % REGION_SIZE, origin.y + idx as PosScalar / REGION_SIZE);
+        });
+    }
+
+    // Size of the file in sectors.
+    pub fn sector_count(&self) -> usize {
+        return self.used_sectors.len();
+    }
+
+    // Falls back to the previous copy if the current one is damaged.
+    pub fn read_chunk(&mut self, pos: ChunkPos) -> Result<Option<Vec<u8>>, RegionError> {
+        let idx = self.index(pos);
+        let location = match self.locations[idx] {
+            Some(location) => location,
+            None => { return Ok(None); }
+        };
+        let error = match self.read_entry(location)? {
+            Ok(data) => { return Ok(Some(data)); }
+            Err(error) => error,
+        };
+        if let Some(backup) = self.backups[idx] {
+            if let Ok(data) = self.read_entry(backup)? {
+                self.recovered.push(pos);
+                return Ok(Some(data));
+            }
+        }
+        return Err(RegionError::Corrupt(format!("chunk {:?}: {}", pos, error)));
+    }
+
+    // The outer error is for failed reads, the inner one for damaged data.
+    fn read_entry(&mut self, location: Location) -> Result<Result<Vec<u8>, String>, RegionError> {
+        let mut data = vec![0; location.count * SECTOR_SIZE];
+        self.file.seek(SeekFrom::Start((location.sector * SECTOR_SIZE) as u64))?;
+        self.file.read_exact(&mut data)?;
+
+        let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        if len < CHUNK_HEADER_BYTES - 4 || len > data.len() - 4 {
+            return Ok(Err(format!("bad length {}", len)));
+        }
+        let checksum = u32::from_le_bytes(data[5..9].try_into().unwrap());
+        let payload = &data[CHUNK_HEADER_BYTES..4 + len];
+        if crc32fast::hash(payload) != checksum {
+            return Ok(Err("checksum mismatch".to_string()));
+        }
+        return Ok(match data[4] {
+            COMPRESSION_NONE => Ok(payload.to_vec()),
+            COMPRESSION_ZLIB => {
+                let mut decompressed = Vec::new();
+                ZlibDecoder::new(payload).read_to_end(&mut decompressed)
+                    .map(|_| decompressed)
+                    .map_err(|e| e.to_string())
+            }
+            other => Err(format!("unknown compression {}", other)),
+        });
+    }
+
+    // Chunks that had to be read from their previous copy since the region was opened.
+    pub fn recovered_chunks(&self) -> &[ChunkPos] {
+        return &self.recovered;
+    }
+
+    // The new data is not visible to other readers of the file until flush is called.
+    pub fn write_chunk(&mut self, pos: ChunkPos, data: &[u8]) -> Result<(), RegionError> {
+        let idx = self.index(pos);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let mut sectors = Vec::with_capacity(compressed.len() + CHUNK_HEADER_BYTES);
+        sectors.extend_from_slice(&((compressed.len() + CHUNK_HEADER_BYTES - 4) as u32).to_le_bytes());
+        sectors.push(COMPRESSION_ZLIB);
+        sectors.extend_from_slice(&crc32fast::hash(&compressed).to_le_bytes());
+        sectors.extend_from_slice(&compressed);
+        let count = sectors.len().div_ceil(SECTOR_SIZE);
+        if count > MAX_SECTORS_PER_CHUNK {
+            return Err(RegionError::ChunkTooLarge(compressed.len()));
+        }
+        sectors.resize(count * SECTOR_SIZE, 0);
+
+        let location = self.allocate(count);
+        self.file.seek(SeekFrom::Start((location.sector * SECTOR_SIZE) as u64))?;
+        self.file.write_all(&sectors)?;
+
+        if let Some(backup) = self.backups[idx].take() {
+            self.pending_release.push(backup);
+        }
+        self.backups[idx] = self.locations[idx];
+        self.locations[idx] = Some(location);
+        self.timestamps[idx] = now();
+        self.pending.insert(idx);
+        return Ok(());
+    }
+
+    pub fn remove_chunk(&mut self, pos: ChunkPos) -> Result<(), RegionError> {
+        let idx = self.index(pos);
+        let removed: Vec<Location> = self.locations[idx].take().into_iter().chain(self.backups[idx].take()).collect();
+        if !removed.is_empty() {
+            self.pending_release.extend(removed);
+            self.timestamps[idx] = 0;
+            self.pending.insert(idx);
+        }
+        return Ok(());
+    }
+
+    // Makes the chunk data durable, then points the header to it.
+    pub fn flush(&mut self) -> Result<(), RegionError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.file.sync_data()?;
+        for idx in std::mem::take(&mut self.pending) {
+            self.write_header_entry(idx)?;
+        }
+        self.file.sync_data()?;
+        for location in std::mem::take(&mut self.pending_release) {
+            self.release(location);
+        }
+        return Ok(());
+    }
+
+    fn write_header_entry(&mut self, idx: usize) -> Result<(), RegionError> {
+        let entries = [
+            (0, self.locations[idx].map_or(0, |l| l.raw())),
+            (1, self.timestamps[idx]),
+            (2, self.backups[idx].map_or(0, |l| l.raw())),
+        ];
+        for (sector, value) in entries.iter() {
+            self.file.seek(SeekFrom::Start((sector * SECTOR_SIZE + idx * 4) as u64))?;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        return Ok(());
+    }
+
+    fn release(&mut self, location: Location) {
+        for used in self.used_sectors[location.sector..location.sector + location.count].iter_mut() {
+            *used = false;
+        }
+    }
+
+    // First fit, grows the file if no run of free sectors is long enough.
+    fn allocate(&mut self, count: usize) -> Location {
+        let mut run = 0;
+        for sector in HEADER_SECTORS..self.used_sectors.len() {
+            run = if self.used_sectors[sector] { 0 } else { run + 1 };
+            if run == count {
+                let location = Location{ sector: sector + 1 - count, count };
+                self.mark_used(location);
+                return location;
+            }
+        }
+        // A free run at the end of the file is extended.
+        let location = Location{ sector: self.used_sectors.len() - run, count };
+        self.used_sectors.resize(location.sector + count, false);
+        self.mark_used(location);
+        return location;
+    }
+
+    fn mark_used(&mut self, location: Location) {
+        for used in self.used_sectors[location.sector..location.sector + location.count].iter_mut() {
+            *used = true;
+        }
+    }
+}
+
+// Directory of region files, opened on first use.
+pub struct RegionStore {
+    dir: PathBuf,
+    regions: HashMap<RegionPos, Region>,
+}
+
+impl RegionStore {
+    pub fn new(dir: &Path) -> Result<Self, RegionError> {
+        std::fs::create_dir_all(dir)?;
+        return Ok(RegionStore{ dir: dir.to_path_buf(), regions: HashMap::new() });
+    }
+
+    pub fn dir(&self) -> &Path {
+        return &self.dir;
+    }
+
+    fn region_path(&self, pos: RegionPos) -> PathBuf {
+        return self.dir.join(pos.file_name());
+    }
+
+    // None if the region file doesn't exist and `create` is false.
+    fn region(&mut self, pos: RegionPos, create: bool) -> Result<Option<&mut Region>, RegionError> {
+        if !self.regions.contains_key(&pos) {
+            let path = self.region_path(pos);
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            let region = Region::open(&path, pos)?;
+            self.regions.insert(pos, region);
+        }
+        return Ok(self.regions.get_mut(&pos));
+    }
+
+    pub fn read_chunk(&mut self, pos: ChunkPos) -> Result<Option<Vec<u8>>, RegionError> {
+        return match self.region(RegionPos::of(pos), false)? {
+            Some(region) => region.read_chunk(pos),
+            None => Ok(None),
+        };
+    }
+
+    pub fn write_chunk(&mut self, pos: ChunkPos, data: &[u8]) -> Result<(), RegionError> {
+        return self.region(RegionPos::of(pos), true)?.unwrap().write_chunk(pos, data);
+    }
+
+    pub fn remove_chunk(&mut self, pos: ChunkPos) -> Result<(), RegionError> {
+        return match self.region(RegionPos::of(pos), false)? {
+            Some(region) => region.remove_chunk(pos),
+            None => Ok(()),
+        };
+    }
+
+    pub fn recovered_chunks(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        return self.regions.values().flat_map(|r| r.recovered_chunks().iter().copied());
+    }
+
+    // Commits all writes since the last call, see Region::flush.
+    pub fn sync(&mut self) -> Result<(), RegionError> {
+        for region in self.regions.values_mut() {
+            region.flush()?;
+        }
+        return atomic::sync_dir(&self.dir).map_err(RegionError::Io);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustcraft-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    // Data that doesn't compress well, so the size on disk is predictable.
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(2654435761) | 1;
+        return (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect();
+    }
+
+    #[test]
+    fn chunks_round_trip_through_regions() {
+        let dir = temp_dir("region-round-trip");
+        let mut store = RegionStore::new(&dir).unwrap();
+        let positions = [ChunkPos::new(0, 0), ChunkPos::new(31, 31), ChunkPos::new(-1, -1), ChunkPos::new(-33, 40)];
+        for (idx, pos) in positions.iter().enumerate() {
+            store.write_chunk(*pos, &noise(1000 * (idx + 1), idx as u32)).unwrap();
+        }
+        // Unflushed writes are visible through the same store.
+        assert_eq!(store.read_chunk(positions[1]).unwrap().unwrap(), noise(2000, 1));
+        store.sync().unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        assert_eq!(store.read_chunk(ChunkPos::new(1, 0)).unwrap(), None);
+        assert_eq!(store.read_chunk(ChunkPos::new(100, 100)).unwrap(), None);
+
+        // Reopening reads everything back from disk.
+        let mut store = RegionStore::new(&dir).unwrap();
+        for (idx, pos) in positions.iter().enumerate() {
+            assert_eq!(store.read_chunk(*pos).unwrap().unwrap(), noise(1000 * (idx + 1), idx as u32));
+        }
+        let region = store.region(RegionPos::of(ChunkPos::new(0, 0)), false).unwrap().unwrap();
+        let mut chunks: Vec<_> = region.chunks().collect();
+        chunks.sort();
+        assert_eq!(chunks, vec![ChunkPos::new(0, 0), ChunkPos::new(31, 31)]);
+        assert!(region.timestamp(ChunkPos::new(0, 0)).unwrap() > 0);
+        assert_eq!(region.timestamp(ChunkPos::new(5, 0)), None);
+
+        store.remove_chunk(ChunkPos::new(-1, -1)).unwrap();
+        store.sync().unwrap();
+        assert_eq!(RegionStore::new(&dir).unwrap().read_chunk(ChunkPos::new(-1, -1)).unwrap(), None);
+        assert_eq!(store.recovered_chunks().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn freed_sectors_are_reused() {
+        let dir = temp_dir("region-sectors");
+        let path = dir.join("region");
+        let mut region = Region::open(&path, RegionPos::of(ChunkPos::new(0, 0))).unwrap();
+        let (a, b, c) = (ChunkPos::new(0, 0), ChunkPos::new(1, 0), ChunkPos::new(2, 0));
+
+        region.write_chunk(a, &noise(3 * SECTOR_SIZE, 1)).unwrap();
+        region.write_chunk(b, &noise(SECTOR_SIZE / 2, 2)).unwrap();
+        region.flush().unwrap();
+        let sectors = region.sector_count();
+        assert_eq!(sectors, HEADER_SECTORS + 4 + 1);
+
+        // The first rewrite keeps the old copy, the second one frees it once flushed.
+        region.write_chunk(a, &noise(SECTOR_SIZE / 2, 3)).unwrap();
+        region.flush().unwrap();
+        assert_eq!(region.sector_count(), sectors + 1);
+        region.write_chunk(a, &noise(SECTOR_SIZE / 2, 4)).unwrap();
+        region.flush().unwrap();
+        assert_eq!(region.sector_count(), sectors + 2);
+        region.write_chunk(c, &noise(2 * SECTOR_SIZE, 5)).unwrap();
+        region.flush().unwrap();
+        assert_eq!(region.sector_count(), sectors + 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, region.sector_count() * SECTOR_SIZE);
+
+        let mut region = Region::open(&path, RegionPos::of(ChunkPos::new(0, 0))).unwrap();
+        assert_eq!(region.read_chunk(a).unwrap().unwrap(), noise(SECTOR_SIZE / 2, 4));
+        assert_eq!(region.read_chunk(b).unwrap().unwrap(), noise(SECTOR_SIZE / 2, 2));
+        assert_eq!(region.read_chunk(c).unwrap().unwrap(), noise(2 * SECTOR_SIZE, 5));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_writes_are_recovered() {
+        let dir = temp_dir("region-torn");
+        let path = dir.join("region");
+        let pos = ChunkPos::new(3, 4);
+        let region_pos = RegionPos::of(pos);
+        {
+            let mut region = Region::open(&path, region_pos).unwrap();
+            region.write_chunk(pos, &noise(5000, 1)).unwrap();
+            region.flush().unwrap();
+            region.write_chunk(pos, &noise(5000, 2)).unwrap();
+            region.flush().unwrap();
+            // Crash before the header is updated
+            region.write_chunk(pos, &noise(5000, 3)).unwrap();
+        }
+        let mut region = Region::open(&path, region_pos).unwrap();
+        assert_eq!(region.read_chunk(pos).unwrap().unwrap(), noise(5000, 2));
+        assert!(region.recovered_chunks().is_empty());
+
+        // Damage the current copy as if it was only partially written.
+        let current = region.locations[chunk_index(pos)].unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        let offset = current.sector * SECTOR_SIZE + 100;
+        data[offset..offset + 100].iter_mut().for_each(|b| *b = 0);
+        std::fs::write(&path, &data).unwrap();
+
+        let mut region = Region::open(&path, region_pos).unwrap();
+        assert_eq!(region.read_chunk(pos).unwrap().unwrap(), noise(5000, 1));
+        assert_eq!(region.recovered_chunks(), &[pos]);
+
+        // Without a previous copy the damage is reported.
+        let backup = region.backups[chunk_index(pos)].unwrap();
+        let offset = backup.sector * SECTOR_SIZE + 100;
+        data[offset..offset + 100].iter_mut().for_each(|b| *b = 0);
+        std::fs::write(&path, &data).unwrap();
+        let mut region = Region::open(&path, region_pos).unwrap();
+        assert!(matches!(region.read_chunk(pos), Err(RegionError::Corrupt(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_headers_are_rejected() {
+        let dir = temp_dir("region-corrupt");
+        let path = dir.join("region");
+        let pos = RegionPos::of(ChunkPos::new(0, 0));
+        {
+            let mut region = Region::open(&path, pos).unwrap();
+            region.write_chunk(ChunkPos::new(0, 0), &noise(100, 1)).unwrap();
+            region.write_chunk(ChunkPos::new(1, 0), &noise(100, 2)).unwrap();
+            region.flush().unwrap();
+        }
+        let valid = std::fs::read(&path).unwrap();
+
+        // Both chunks pointing to the same sector
+        let mut overlapping = valid.clone();
+        overlapping.copy_within(0..4, 4);
+        std::fs::write(&path, &overlapping).unwrap();
+        assert!(matches!(Region::open(&path, pos), Err(RegionError::Corrupt(_))));
+
+        let mut outside = valid.clone();
+        outside[..4].copy_from_slice(&Location{ sector: 100, count: 1 }.raw().to_le_bytes());
+        std::fs::write(&path, &outside).unwrap();
+        assert!(matches!(Region::open(&path, pos), Err(RegionError::Corrupt(_))));
+
+        std::fs::write(&path, &valid[..HEADER_SECTORS * SECTOR_SIZE - 1]).unwrap();
+        assert!(matches!(Region::open(&path, pos), Err(RegionError::Corrupt(_))));
+
+        // A partial sector at the end is dropped, the committed chunks are still there.
+        let mut partial = valid.clone();
+        partial.extend_from_slice(&[7; SECTOR_SIZE / 2]);
+        std::fs::write(&path, &partial).unwrap();
+        let mut region = Region::open(&path, pos).unwrap();
+        assert_eq!(region.read_chunk(ChunkPos::new(0, 0)).unwrap().unwrap(), noise(100, 1));
+        assert_eq!(region.read_chunk(ChunkPos::new(1, 0)).unwrap().unwrap(), noise(100, 2));
+        region.write_chunk(ChunkPos::new(2, 0), &noise(100, 3)).unwrap();
+        region.flush().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize % SECTOR_SIZE, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,14 +6,14 @@ extern crate nalgebra as na;
 
 // MOD STATEMENTS
 mod features;
-mod io;
 mod system;
-mod util;
 
 // PUB MOD STATEMENTS
 pub mod core;
+pub mod io;
 pub mod model;
 pub mod task;
+pub mod util;
 pub mod engine;
 
 // FEATURES STATEMENTS
@@ -0,0 +1,148 @@
+use crate::model::heightmap::COLUMNS;
+use crate::model::config;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Vegetation {
+    None,
+    TallGrass,
+    Trees,
+    Cactus,
+}
+
+// What a biome looks like. Terrain parameters are blended across biome borders, the rest is per column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiomeProperties {
+    pub name: &'static str,
+    // Top block of columns above the sea level and the blocks right below it
+    pub surface: &'static str,
+    pub filler: &'static str,
+    pub vegetation: Vegetation,
+    // Fraction of the columns with a plant or tree on them
+    pub vegetation_density: f64,
+    // 0xRRGGBB, multiplied with the grey textures of grass, leaves and water
+    pub grass_tint: u32,
+    pub foliage_tint: u32,
+    pub water_tint: u32,
+    // Added to the height of the ground, in blocks
+    pub height_offset: f64,
+    // Amplitude of the hills, in blocks
+    pub hill_scale: f64,
+    // Height of the mountain ridges where mountain ranges rise, in blocks
+    pub mountain_scale: f64,
+}
+
+// Stored by id in chunks, ids must never be reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Biome {
+    Ocean,
+    Beach,
+    Plains,
+    Forest,
+    Desert,
+    Taiga,
+    SnowyPlains,
+    Swamp,
+    Mountains,
+}
+
+const PROPERTIES: [BiomeProperties; 9] = [
+    BiomeProperties{ name: "ocean", surface: "sand", filler: "sand", vegetation: Vegetation::None, vegetation_density: 0.0,
+        grass_tint: 0x8eb971, foliage_tint: 0x71a74d, water_tint: 0x3f76e4, height_offset: -4.0, hill_scale: 3.0, mountain_scale: 0.0 },
+    BiomeProperties{ name: "beach", surface: "sand", filler: "sand", vegetation: Vegetation::None, vegetation_density: 0.0,
+        grass_tint: 0x91bd59, foliage_tint: 0x77ab2f, water_tint: 0x3f76e4, height_offset: 0.0, hill_scale: 1.0, mountain_scale: 0.0 },
+    BiomeProperties{ name: "plains", surface: "grass_block", filler: "dirt", vegetation: Vegetation::TallGrass, vegetation_density: 0.1,
+        grass_tint: 0x91bd59, foliage_tint: 0x77ab2f, water_tint: 0x3f76e4, height_offset: 1.0, hill_scale: 4.0, mountain_scale: 40.0 },
+    BiomeProperties{ name: "forest", surface: "grass_block", filler: "dirt", vegetation: Vegetation::Trees, vegetation_density: 0.03,
+        grass_tint: 0x79c05a, foliage_tint: 0x59ae30, water_tint: 0x3f76e4, height_offset: 2.0, hill_scale: 8.0, mountain_scale: 40.0 },
+    BiomeProperties{ name: "desert", surface: "sand", filler: "sand", vegetation: Vegetation::Cactus, vegetation_density: 0.004,
+        grass_tint: 0xbfb755, foliage_tint: 0xaea42a, water_tint: 0x32a598, height_offset: 2.0, hill_scale: 5.0, mountain_scale: 40.0 },
+    BiomeProperties{ name: "taiga", surface: "grass_block", filler: "dirt", vegetation: Vegetation::Trees, vegetation_density: 0.02,
+        grass_tint: 0x86b783, foliage_tint: 0x68a464, water_tint: 0x287082, height_offset: 3.0, hill_scale: 10.0, mountain_scale: 40.0 },
+    BiomeProperties{ name: "snowy_plains", surface: "snow_block", filler: "dirt", vegetation: Vegetation::Trees, vegetation_density: 0.002,
+        grass_tint: 0x80b497, foliage_tint: 0x60a17b, water_tint: 0x3d57d6, height_offset: 1.0, hill_scale: 4.0, mountain_scale: 40.0 },
+    BiomeProperties{ name: "swamp", surface: "grass_block", filler: "dirt", vegetation: Vegetation::Trees, vegetation_density: 0.008,
+        grass_tint: 0x6a7039, foliage_tint: 0x6a7039, water_tint: 0x617b64, height_offset: -1.0, hill_scale: 1.0, mountain_scale: 10.0 },
+    BiomeProperties{ name: "mountains", surface: "grass_block", filler: "dirt", vegetation: Vegetation::TallGrass, vegetation_density: 0.02,
+        grass_tint: 0x8ab689, foliage_tint: 0x6da36b, water_tint: 0x3f76e4, height_offset: 4.0, hill_scale: 12.0, mountain_scale: 110.0 },
+];
+
+impl Biome {
+    pub const ALL: [Biome; 9] = [
+        Biome::Ocean, Biome::Beach, Biome::Plains, Biome::Forest, Biome::Desert,
+        Biome::Taiga, Biome::SnowyPlains, Biome::Swamp, Biome::Mountains,
+    ];
+
+    // Of columns in chunks that were stored before biomes existed
+    pub const DEFAULT: Biome = Biome::Plains;
+
+    pub fn id(&self) -> u8 {
+        return *self as u8;
+    }
+
+    pub fn from_id(id: u8) -> Option<Biome> {
+        return Biome::ALL.get(id as usize).copied();
+    }
+
+    pub fn properties(&self) -> &'static BiomeProperties {
+        return &PROPERTIES[*self as usize];
+    }
+
+    pub fn name(&self) -> &'static str {
+        return self.properties().name;
+    }
+}
+
+// Biome of every column of a chunk, indexed like Heightmap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiomeMap {
+    biomes: Vec<Biome>,
+}
+
+impl BiomeMap {
+    pub fn new(biome: Biome) -> Self {
+        return BiomeMap{ biomes: vec![biome; COLUMNS] };
+    }
+
+    // Returns None if the number of columns is wrong.
+    pub fn from_biomes(biomes: Vec<Biome>) -> Option<Self> {
+        if biomes.len() != COLUMNS {
+            return None;
+        }
+        return Some(BiomeMap{ biomes });
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        return &self.biomes;
+    }
+
+    fn column(x: u8, y: u8) -> usize {
+        return y as usize * config::SUBCHUNK_SIZE as usize + x as usize;
+    }
+
+    pub fn biome(&self, x: u8, y: u8) -> Biome {
+        return self.biomes[BiomeMap::column(x, y)];
+    }
+
+    pub fn set_biome(&mut self, x: u8, y: u8, biome: Biome) {
+        self.biomes[BiomeMap::column(x, y)] = biome;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::registry::BlockRegistry;
+
+    #[test]
+    fn biomes_are_consistent() {
+        let registry = BlockRegistry::builtin();
+        for (index, biome) in Biome::ALL.iter().enumerate() {
+            assert_eq!(biome.id() as usize, index);
+            assert_eq!(Biome::from_id(biome.id()), Some(*biome));
+            assert!(registry.block(biome.properties().surface).is_some(), "{}", biome.name());
+            assert!(registry.block(biome.properties().filler).is_some(), "{}", biome.name());
+        }
+        assert_eq!(Biome::from_id(Biome::ALL.len() as u8), None);
+        assert_eq!(Biome::SnowyPlains.name(), "snowy_plains");
+    }
+}
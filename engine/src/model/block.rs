@@ -1,43 +1,264 @@
 
-pub enum BlockType {
-    Air,
-    Grass
+use crate::core::*;
+use crate::model::block_state::StateError;
+use crate::model::registry::BlockRegistry;
+use nalgebra::Vector3;
+
+pub type BlockData = u32;
+
+// Numeric block id, assigned by the BlockRegistry. Id 0 is always air.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(u16);
+
+impl BlockId {
+    pub const AIR: BlockId = BlockId(0);
+    pub const COUNT: usize = 1 << TYPE_BITS.size;
+
+    pub fn new(raw: u16) -> Option<Self> {
+        if raw as usize >= BlockId::COUNT {
+            return None;
+        }
+        return Some(BlockId(raw));
+    }
+
+    pub fn raw(&self) -> u16 {
+        return self.0;
+    }
+
+    pub fn index(&self) -> usize {
+        return self.0 as usize;
+    }
 }
 
-type BlockData = u16;
+// Cube faces, in the order of their VISIBLE_BITS. The world is z-up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Face {
+    East,  // +x
+    West,  // -x
+    North, // +y
+    South, // -y
+    Up,    // +z
+    Down,  // -z
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::East, Face::West, Face::North, Face::South, Face::Up, Face::Down];
 
-struct BitRange {
-    begin: u8,
-    size: u8
+    pub fn offset(&self) -> Vector3<PosScalar> {
+        return match self {
+            Face::East => Vector3::new(1, 0, 0),
+            Face::West => Vector3::new(-1, 0, 0),
+            Face::North => Vector3::new(0, 1, 0),
+            Face::South => Vector3::new(0, -1, 0),
+            Face::Up => Vector3::new(0, 0, 1),
+            Face::Down => Vector3::new(0, 0, -1),
+        };
+    }
+
+    pub fn opposite(&self) -> Face {
+        return match self {
+            Face::East => Face::West,
+            Face::West => Face::East,
+            Face::North => Face::South,
+            Face::South => Face::North,
+            Face::Up => Face::Down,
+            Face::Down => Face::Up,
+        };
+    }
+
+    pub fn bit(&self) -> u8 {
+        return 1 << (*self as u8);
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct BitRange {
+    pub(crate) begin: u8,
+    pub(crate) size: u8
+}
+
+impl BitRange {
+    pub(crate) const fn mask(&self) -> BlockData {
+        return ((1u64 << self.size) - 1) as BlockData;
+    }
+
+    pub(crate) const fn get(&self, data: BlockData) -> BlockData {
+        return (data >> self.begin) & self.mask();
+    }
+
+    pub(crate) const fn set(&self, data: BlockData, value: BlockData) -> BlockData {
+        let mask = self.mask();
+        return (data & !(mask << self.begin)) | ((value & mask) << self.begin);
+    }
 }
 
 const TYPE_BITS: BitRange = BitRange{begin:0, size:10};
 const VISIBLE_BITS: BitRange = BitRange{begin:10, size:6};
+const STATE_BITS: BitRange = BitRange{begin:16, size:16};
+
+pub const STATE_COUNT: usize = 1 << STATE_BITS.size;
 
-const fn validate_bit_range<T>(ranges: &[BitRange], ) -> bool {
-    // let bit_mask: T = 0 as T;
-    
-    // let total_bits = std::mem::size_of::<BlockData>() * 8;
-    // let mut used_bits = 0;
-    // for range in ranges:
-    // used_bits
+// Checks that every range is non-empty, fits in T and doesn't overlap any other range.
+const fn validate_bit_range<T>(ranges: &[BitRange]) -> bool {
+    let total_bits = (std::mem::size_of::<T>() * 8) as u32;
+    let mut used_bits: u64 = 0;
+    let mut i = 0;
+    while i < ranges.len() {
+        let range = ranges[i];
+        if range.size == 0 || range.begin as u32 + range.size as u32 > total_bits {
+            return false;
+        }
+        let range_bits = ((1u64 << range.size) - 1) << range.begin;
+        if used_bits & range_bits != 0 {
+            return false;
+        }
+        used_bits |= range_bits;
+        i += 1;
+    }
     return true;
 }
 
-//const ALL_BITS: usize = TYPE_BITS + VISIBLE_BITS;
-const_assert!( validate_bit_range::<BlockData>(&[TYPE_BITS, VISIBLE_BITS]) );
+const_assert!( validate_bit_range::<BlockData>(&[TYPE_BITS, VISIBLE_BITS, STATE_BITS]) );
+const_assert!( Face::ALL.len() == VISIBLE_BITS.size as usize );
+
+// Visibility mask of a block given the opacity of its neighbours, air has no visible faces.
+pub fn visible_faces<F: Fn(Face) -> bool>(block: &Block, neighbour_opaque: F) -> u8 {
+    if block.is_air() {
+        return 0;
+    }
+    let mut mask = 0;
+    for face in Face::ALL.iter() {
+        if !neighbour_opaque(*face) {
+            mask |= face.bit();
+        }
+    }
+    return mask;
+}
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Block {
     data: BlockData
 }
 
 impl Block {
+    pub const AIR: Block = Block{ data: 0 };
+
     pub fn new() -> Self { return Block{ data:0 }; }
 
-    pub fn block_type(&self) -> BlockType {
-        // TODO
-        return BlockType::Air;
+    pub fn with_id(id: BlockId) -> Self {
+        let mut block = Block::new();
+        block.set_block_id(id);
+        return block;
+    }
+
+    pub fn data(&self) -> BlockData {
+        return self.data;
+    }
+
+    pub fn block_id(&self) -> BlockId {
+        return BlockId(TYPE_BITS.get(self.data) as u16);
+    }
+
+    pub fn set_block_id(&mut self, id: BlockId) {
+        self.data = TYPE_BITS.set(self.data, id.raw() as BlockData);
+    }
+
+    pub fn is_air(&self) -> bool {
+        return self.block_id() == BlockId::AIR;
+    }
+
+    // Index into the block's StateLayout, see BlockRegistry::layout.
+    pub fn state(&self) -> u16 {
+        return STATE_BITS.get(self.data) as u16;
     }
-}
\ No newline at end of file
+
+    pub fn set_state(&mut self, state: u16) {
+        self.data = STATE_BITS.set(self.data, state as BlockData);
+    }
+
+    pub fn get<'r>(&self, registry: &'r BlockRegistry, property: &str) -> Option<&'r str> {
+        return registry.layout(self.block_id()).value(self.state(), property);
+    }
+
+    pub fn with(&self, registry: &BlockRegistry, property: &str, value: &str) -> Result<Block, StateError> {
+        let state = registry.layout(self.block_id()).with(self.state(), property, value)?;
+        let mut block = *self;
+        block.set_state(state);
+        return Ok(block);
+    }
+
+    // One bit per cube face.
+    pub fn visible_mask(&self) -> u8 {
+        return VISIBLE_BITS.get(self.data) as u8;
+    }
+
+    pub fn set_visible_mask(&mut self, mask: u8) {
+        debug_assert!((mask as BlockData) <= VISIBLE_BITS.mask());
+        self.data = VISIBLE_BITS.set(self.data, mask as BlockData);
+    }
+
+    pub fn is_face_visible(&self, face: Face) -> bool {
+        return self.visible_mask() & face.bit() != 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_bit_range_rejects_bad_layouts() {
+        assert!(validate_bit_range::<BlockData>(&[TYPE_BITS, VISIBLE_BITS, STATE_BITS]));
+        assert!(!validate_bit_range::<BlockData>(&[BitRange{begin:0, size:10}, BitRange{begin:9, size:2}]));
+        assert!(!validate_bit_range::<BlockData>(&[BitRange{begin:26, size:7}]));
+        assert!(!validate_bit_range::<u8>(&[TYPE_BITS]));
+        assert!(!validate_bit_range::<BlockData>(&[BitRange{begin:3, size:0}]));
+    }
+
+    #[test]
+    fn block_ids_fit_type_bits() {
+        let last = BlockId::new((BlockId::COUNT - 1) as u16).unwrap();
+        assert_eq!(Block::with_id(last).block_id(), last);
+        assert_eq!(BlockId::new(BlockId::COUNT as u16), None);
+    }
+
+    #[test]
+    fn faces_map_to_visible_bits() {
+        let mut mask = 0;
+        for face in Face::ALL.iter() {
+            assert_eq!(face.offset() + face.opposite().offset(), Vector3::zeros());
+            assert_eq!(mask & face.bit(), 0);
+            mask |= face.bit();
+        }
+        assert_eq!(mask as BlockData, VISIBLE_BITS.mask());
+
+        let stone = Block::with_id(BlockId::new(1).unwrap());
+        let mask = visible_faces(&stone, |face| face != Face::Up && face != Face::East);
+        assert_eq!(mask, Face::Up.bit() | Face::East.bit());
+        let mut block = stone;
+        block.set_visible_mask(mask);
+        assert!(block.is_face_visible(Face::Up));
+        assert!(!block.is_face_visible(Face::Down));
+        assert_eq!(visible_faces(&Block::new(), |_| false), 0);
+    }
+
+    #[test]
+    fn fields_are_independent() {
+        let grass = BlockId::new(3).unwrap();
+        let mut block = Block::with_id(grass);
+        block.set_visible_mask(0b101010);
+        block.set_state(0xBEEF);
+        assert_eq!(block.block_id(), grass);
+        assert_eq!(block.visible_mask(), 0b101010);
+        assert_eq!(block.state(), 0xBEEF);
+
+        block.set_block_id(BlockId::AIR);
+        assert!(block.is_air());
+        assert_eq!(block.visible_mask(), 0b101010);
+
+        block.set_visible_mask(0);
+        block.set_state(0);
+        assert_eq!(block.data(), 0);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::core::*;
+use crate::model::config;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const CHEST_SLOTS: usize = 27;
+const SIGN_LINES: usize = 4;
+const SMELT_TIME: f32 = 10.0; // In seconds
+
+// Set in the block definition of blocks that need a block entity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockEntityKind {
+    Chest,
+    Sign,
+    Furnace,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemStack {
+    pub item: String,
+    pub count: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChestData {
+    pub slots: Vec<Option<ItemStack>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignData {
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FurnaceData {
+    pub input: Option<ItemStack>,
+    pub fuel: Option<ItemStack>,
+    pub output: Option<ItemStack>,
+    pub burn_time_left: f32, // In seconds
+    pub smelt_progress: f32, // From 0 to 1
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BlockEntity {
+    Chest(ChestData),
+    Sign(SignData),
+    Furnace(FurnaceData),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockEntityStore {
+    // Keyed by the chunk local block index, see BlockEntityStore::local_key
+    entities: BTreeMap<u32, BlockEntity>,
+}
+
+fn smelting_result(item: &str) -> Option<&'static str> {
+    return match item {
+        "sand" => Some("glass"),
+        "log" => Some("charcoal"),
+        "cobblestone" => Some("stone"),
+        _ => None,
+    };
+}
+
+fn fuel_burn_time(item: &str) -> Option<f32> {
+    return match item {
+        "log" => Some(15.0),
+        "charcoal" | "coal" => Some(80.0),
+        _ => None,
+    };
+}
+
+fn take_one(stack: &mut Option<ItemStack>) {
+    if let Some(s) = stack.as_mut() {
+        // Stacks of 0 items are treated as empty slots
+        s.count = s.count.saturating_sub(1);
+        if s.count == 0 {
+            *stack = None;
+        }
+    }
+}
+
+impl ItemStack {
+    pub fn new(item: &str, count: u8) -> Self {
+        return ItemStack{ item: item.to_string(), count };
+    }
+}
+
+impl FurnaceData {
+    fn can_smelt(&self) -> bool {
+        let result = match self.input.as_ref().and_then(|s| smelting_result(&s.item)) {
+            Some(result) => result,
+            None => { return false; }
+        };
+        return match self.output.as_ref() {
+            Some(output) => output.item == result && output.count < u8::MAX,
+            None => true,
+        };
+    }
+
+    // Returns whether anything changed, an idle furnace stays as it is.
+    fn tick(&mut self, dt: f32) -> bool {
+        if self.burn_time_left <= 0.0 && self.can_smelt() {
+            if let Some(burn_time) = self.fuel.as_ref().and_then(|s| fuel_burn_time(&s.item)) {
+                self.burn_time_left = burn_time;
+                take_one(&mut self.fuel);
+            }
+        }
+
+        if self.burn_time_left <= 0.0 {
+            let changed = self.smelt_progress != 0.0;
+            self.smelt_progress = 0.0;
+            return changed;
+        }
+        self.burn_time_left = (self.burn_time_left - dt).max(0.0);
+
+        if !self.can_smelt() {
+            self.smelt_progress = 0.0;
+            return true;
+        }
+        self.smelt_progress += dt / SMELT_TIME;
+        if self.smelt_progress >= 1.0 {
+            self.smelt_progress = 0.0;
+            let result = smelting_result(&self.input.as_ref().unwrap().item).unwrap();
+            take_one(&mut self.input);
+            match self.output.as_mut() {
+                Some(output) => { output.count += 1; }
+                None => { self.output = Some(ItemStack::new(result, 1)); }
+            }
+        }
+        return true;
+    }
+}
+
+impl BlockEntity {
+    pub fn create(kind: BlockEntityKind) -> Self {
+        return match kind {
+            BlockEntityKind::Chest => BlockEntity::Chest(ChestData{ slots: vec![None; CHEST_SLOTS] }),
+            BlockEntityKind::Sign => BlockEntity::Sign(SignData{ lines: vec![String::new(); SIGN_LINES] }),
+            BlockEntityKind::Furnace => BlockEntity::Furnace(FurnaceData::default()),
+        };
+    }
+
+    pub fn kind(&self) -> BlockEntityKind {
+        return match self {
+            BlockEntity::Chest(_) => BlockEntityKind::Chest,
+            BlockEntity::Sign(_) => BlockEntityKind::Sign,
+            BlockEntity::Furnace(_) => BlockEntityKind::Furnace,
+        };
+    }
+
+    // Every non empty slot of the entity.
+    pub fn items_mut(&mut self) -> impl Iterator<Item = &mut ItemStack> {
+        let slots: Vec<&mut Option<ItemStack>> = match self {
+            BlockEntity::Chest(chest) => chest.slots.iter_mut().collect(),
+            BlockEntity::Sign(_) => Vec::new(),
+            BlockEntity::Furnace(furnace) => vec![&mut furnace.input, &mut furnace.fuel, &mut furnace.output],
+        };
+        return slots.into_iter().filter_map(|slot| slot.as_mut());
+    }
+
+    // Returns whether the entity changed.
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        return match self {
+            BlockEntity::Furnace(furnace) => furnace.tick(dt.as_secs_f32()),
+            BlockEntity::Chest(_) | BlockEntity::Sign(_) => false,
+        };
+    }
+}
+
+impl BlockEntityStore {
+    pub fn new() -> Self {
+        return BlockEntityStore::default();
+    }
+
+    fn local_key(pos: BlockPos) -> u32 {
+        let size = config::SUBCHUNK_SIZE;
+        let local = pos.local();
+        assert!(pos.z >= 0);
+        return pos.z as u32 * size * size + local.y as u32 * size + local.x as u32;
+    }
+
+    fn key_to_local(key: u32) -> BlockPos {
+        let size = config::SUBCHUNK_SIZE;
+        return BlockPos::new((key % size) as PosScalar, (key / size % size) as PosScalar, (key / (size * size)) as PosScalar);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entities.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entities.is_empty();
+    }
+
+    pub fn get(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        return self.entities.get(&BlockEntityStore::local_key(pos));
+    }
+
+    pub fn get_mut(&mut self, pos: BlockPos) -> Option<&mut BlockEntity> {
+        return self.entities.get_mut(&BlockEntityStore::local_key(pos));
+    }
+
+    pub fn insert(&mut self, pos: BlockPos, entity: BlockEntity) -> Option<BlockEntity> {
+        return self.entities.insert(BlockEntityStore::local_key(pos), entity);
+    }
+
+    pub fn remove(&mut self, pos: BlockPos) -> Option<BlockEntity> {
+        return self.entities.remove(&BlockEntityStore::local_key(pos));
+    }
+
+    // Yields positions relative to the chunk origin.
+    pub fn iter(&self) -> impl Iterator<Item = (BlockPos, &BlockEntity)> {
+        return self.entities.iter().map(|(key, entity)| (BlockEntityStore::key_to_local(*key), entity));
+    }
+
+    pub fn items_mut(&mut self) -> impl Iterator<Item = &mut ItemStack> {
+        return self.entities.values_mut().flat_map(|entity| entity.items_mut());
+    }
+
+    // Returns whether any entity changed.
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        let mut changed = false;
+        for entity in self.entities.values_mut() {
+            changed |= entity.tick(dt);
+        }
+        return changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_is_keyed_by_local_position() {
+        let mut store = BlockEntityStore::new();
+        store.insert(BlockPos::new(-1, 17, 40), BlockEntity::create(BlockEntityKind::Chest));
+        assert!(store.get(BlockPos::new(15, 1, 40)).is_some());
+        assert_eq!(store.iter().next().unwrap().0, BlockPos::new(15, 1, 40));
+
+        let old = store.insert(BlockPos::new(15, 1, 40), BlockEntity::create(BlockEntityKind::Sign));
+        assert_eq!(old.unwrap().kind(), BlockEntityKind::Chest);
+        assert_eq!(store.len(), 1);
+        assert!(store.remove(BlockPos::new(15, 1, 40)).is_some());
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn taking_from_empty_stacks_clears_them() {
+        let mut stack = Some(ItemStack::new("log", 0));
+        take_one(&mut stack);
+        assert_eq!(stack, None);
+        let mut stack = Some(ItemStack::new("log", 2));
+        take_one(&mut stack);
+        assert_eq!(stack, Some(ItemStack::new("log", 1)));
+    }
+
+    #[test]
+    fn furnace_smelts_while_fuelled() {
+        let mut entity = BlockEntity::Furnace(FurnaceData{
+            input: Some(ItemStack::new("sand", 2)),
+            fuel: Some(ItemStack::new("log", 1)),
+            ..Default::default()
+        });
+        for _ in 0..40 {
+            entity.tick(Duration::from_millis(500));
+        }
+        // Out of fuel, further ticks change nothing
+        assert!(!entity.tick(Duration::from_millis(500)));
+        match entity {
+            BlockEntity::Furnace(furnace) => {
+                // One log burns for 15s, which is enough for a single item.
+                assert_eq!(furnace.output, Some(ItemStack::new("glass", 1)));
+                assert_eq!(furnace.input, Some(ItemStack::new("sand", 1)));
+                assert_eq!(furnace.fuel, None);
+                assert_eq!(furnace.burn_time_left, 0.0);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::model::block::STATE_COUNT;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    UnknownBlock(String),
+    UnknownProperty(String),
+    InvalidValue(String, String),
+    DuplicateProperty(String),
+    EmptyProperty(String),
+    TooManyStates(usize),
+}
+
+// A block property and its allowed values, the first value is the default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PropertyDefinition {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+// Maps every combination of property values of a block to a compact state id.
+// The state id is a mixed radix number, with the first property as the least significant digit.
+#[derive(Debug, Clone, Default)]
+pub struct StateLayout {
+    properties: Vec<PropertyDefinition>,
+    strides: Vec<u32>,
+    state_count: u32,
+}
+
+// Registry independent description of a block state, used to store blocks on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockStateDescriptor {
+    pub name: String,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl StateLayout {
+    pub fn new(properties: Vec<PropertyDefinition>) -> Result<Self, StateError> {
+        let mut strides = Vec::with_capacity(properties.len());
+        let mut state_count: usize = 1;
+        for (idx, property) in properties.iter().enumerate() {
+            if property.values.is_empty() {
+                return Err(StateError::EmptyProperty(property.name.clone()));
+            }
+            if properties[..idx].iter().any(|p| p.name == property.name) {
+                return Err(StateError::DuplicateProperty(property.name.clone()));
+            }
+            strides.push(state_count as u32);
+            state_count *= property.values.len();
+            if state_count > STATE_COUNT {
+                return Err(StateError::TooManyStates(state_count));
+            }
+        }
+        return Ok(StateLayout{ properties, strides, state_count: state_count as u32 });
+    }
+
+    pub fn state_count(&self) -> u32 {
+        return self.state_count;
+    }
+
+    pub fn properties(&self) -> &[PropertyDefinition] {
+        return &self.properties;
+    }
+
+    fn property_index(&self, name: &str) -> Option<usize> {
+        return self.properties.iter().position(|p| p.name == name);
+    }
+
+    fn value_index(&self, state: u16, property: usize) -> usize {
+        let count = self.properties[property].values.len() as u32;
+        return ((state as u32 / self.strides[property]) % count) as usize;
+    }
+
+    pub fn value(&self, state: u16, name: &str) -> Option<&str> {
+        let property = self.property_index(name)?;
+        return Some(&self.properties[property].values[self.value_index(state, property)]);
+    }
+
+    pub fn values(&self, state: u16) -> impl Iterator<Item = (&str, &str)> + '_ {
+        return self.properties.iter().enumerate()
+            .map(move |(idx, p)| (p.name.as_str(), p.values[self.value_index(state, idx)].as_str()));
+    }
+
+    pub fn with(&self, state: u16, name: &str, value: &str) -> Result<u16, StateError> {
+        let property = self.property_index(name).ok_or_else(|| StateError::UnknownProperty(name.to_string()))?;
+        let new_idx = self.properties[property].values.iter().position(|v| v == value)
+            .ok_or_else(|| StateError::InvalidValue(name.to_string(), value.to_string()))?;
+        let old_idx = self.value_index(state, property);
+        let stride = self.strides[property];
+        return Ok((state as u32 - old_idx as u32 * stride + new_idx as u32 * stride) as u16);
+    }
+
+    // Properties missing from the map keep their default value, unknown ones are ignored
+    // so that data written by older definitions still loads.
+    pub fn state_from(&self, values: &BTreeMap<String, String>) -> Result<u16, StateError> {
+        let mut state = 0;
+        for (name, value) in values.iter() {
+            if self.property_index(name).is_some() {
+                state = self.with(state, name, value)?;
+            }
+        }
+        return Ok(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(name: &str, values: &[&str]) -> PropertyDefinition {
+        return PropertyDefinition{ name: name.to_string(), values: values.iter().map(|v| v.to_string()).collect() };
+    }
+
+    #[test]
+    fn every_combination_has_unique_state() {
+        let layout = StateLayout::new(vec![
+            property("facing", &["north", "south", "east", "west"]),
+            property("powered", &["false", "true"]),
+        ]).unwrap();
+        assert_eq!(layout.state_count(), 8);
+
+        let mut seen = std::collections::HashSet::new();
+        for facing in ["north", "south", "east", "west"].iter() {
+            for powered in ["false", "true"].iter() {
+                let state = layout.with(0, "facing", facing).unwrap();
+                let state = layout.with(state, "powered", powered).unwrap();
+                assert_eq!(layout.value(state, "facing"), Some(*facing));
+                assert_eq!(layout.value(state, "powered"), Some(*powered));
+                assert!(seen.insert(state));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_layouts_and_values_are_rejected() {
+        assert_eq!(StateLayout::new(vec![property("age", &[])]).err(), Some(StateError::EmptyProperty("age".to_string())));
+        assert!(StateLayout::new(vec![property("a", &["0"]), property("a", &["1"])]).is_err());
+        let values: Vec<String> = (0..300).map(|v| v.to_string()).collect();
+        let big = PropertyDefinition{ name: "big".to_string(), values };
+        let other = PropertyDefinition{ name: "other".to_string(), ..big.clone() };
+        assert_eq!(StateLayout::new(vec![big, other]).err(), Some(StateError::TooManyStates(90000)));
+
+        let layout = StateLayout::new(vec![property("half", &["bottom", "top"])]).unwrap();
+        assert!(layout.with(0, "half", "middle").is_err());
+        assert!(layout.with(0, "facing", "north").is_err());
+    }
+}
@@ -1,69 +1,731 @@
 use crate::core::*;
 use crate::model::config;
 use crate::model::block;
-use nalgebra::{Vector2, Vector3};
+use crate::model::biome::{Biome, BiomeMap};
+use crate::model::block_entity::{BlockEntity, BlockEntityStore};
+use crate::model::generator::WorldGenerator;
+use crate::model::heightmap::{Heightmap, HeightmapKind};
+use crate::model::light::{self, LightArray, LightKind};
+use crate::model::palette::PalettedStorage;
+use crate::model::registry::BlockRegistry;
+
+use std::sync::atomic::{AtomicU64, Ordering};
 
-const SUBCHUNK_VOLUME: usize = (config::SUBCHUNK_SIZE * config::SUBCHUNK_SIZE * config::SUBCHUNK_SIZE) as usize;
 const SUBCHUNK_COUNT: usize = config::BUILD_LIMIT as usize;
 
-#[derive(Copy, Clone)]
+// Every modification of a sub-chunk is stamped with a new revision,
+// systems remember the revision they last saw and ask for what changed since.
+static REVISION: AtomicU64 = AtomicU64::new(0);
+
+pub fn current_revision() -> u64 {
+    return REVISION.load(Ordering::Acquire);
+}
+
+fn next_revision() -> u64 {
+    return REVISION.fetch_add(1, Ordering::AcqRel) + 1;
+}
+
+#[derive(Clone)]
 pub struct SubChunk {
-    blocks: [block::Block; SUBCHUNK_VOLUME]
+    blocks: PalettedStorage,
+    // Not allocated until something computes light for the sub-chunk
+    sky_light: Option<LightArray>,
+    block_light: Option<LightArray>,
+    dirty: bool,
+    revision: u64,
 }
 
-#[derive(Copy, Clone)]
+// Sub-chunks are only allocated once something other than air is written to them.
+#[derive(Clone)]
 pub struct Chunk {
-    pos: Vector2<PosScalar>,
-    sub_chunks: [SubChunk; SUBCHUNK_COUNT]
+    pos: ChunkPos,
+    sub_chunks: Vec<Option<Box<SubChunk>>>,
+    block_entities: BlockEntityStore,
+    block_entities_dirty: bool,
+    // Indexed by HeightmapKind::index
+    heightmaps: Vec<Heightmap>,
+    biomes: BiomeMap,
+    biomes_dirty: bool,
 }
 
 impl SubChunk {
-    pub fn empty() -> Self { 
-        return SubChunk{
-            blocks:[block::Block::new(); SUBCHUNK_VOLUME],
-        }; 
+    pub fn empty() -> Self {
+        return SubChunk::from_storage(PalettedStorage::new(LocalPos::VOLUME, block::Block::new())).unwrap();
+    }
+
+    // Returns None if the storage doesn't have a block for every position of a sub-chunk.
+    pub fn from_storage(blocks: PalettedStorage) -> Option<Self> {
+        if blocks.len() != LocalPos::VOLUME {
+            return None;
+        }
+        return Some(SubChunk{ blocks, sky_light: None, block_light: None, dirty: false, revision: 0 });
+    }
+
+    pub fn block(&self, local_pos: LocalPos) -> Option<&block::Block> {
+        return Some(self.blocks.get(local_pos.index()));
+    }
+
+    pub fn set_block(&mut self, local_pos: LocalPos, block: block::Block) {
+        if self.blocks.set(local_pos.index(), block) {
+            self.dirty = true;
+            self.revision = next_revision();
+        }
+    }
+
+    // Visible faces follow from the neighbours and are fixed up whenever chunks are loaded next to
+    // each other, so they alone don't need a save. Meshes still see the change through the revision.
+    pub(crate) fn set_visible_mask(&mut self, local_pos: LocalPos, mask: u8) {
+        let mut block = *self.blocks.get(local_pos.index());
+        block.set_visible_mask(mask);
+        if self.blocks.set(local_pos.index(), block) {
+            self.revision = next_revision();
+        }
+    }
+
+    pub fn storage(&self) -> &PalettedStorage {
+        return &self.blocks;
+    }
+
+    pub fn light(&self, kind: LightKind) -> Option<&LightArray> {
+        return match kind {
+            LightKind::Sky => self.sky_light.as_ref(),
+            LightKind::Block => self.block_light.as_ref(),
+        };
+    }
+
+    // 0 when no light was computed.
+    pub fn light_level(&self, kind: LightKind, local_pos: LocalPos) -> u8 {
+        return self.light(kind).map_or(0, |light| light.get(local_pos));
     }
 
-    fn pos_to_idx(local_pos: Vector3<PosScalar>) -> usize {
-        let signed_map_size = config::MAP_SIZE as PosScalar;
-        let signed_layer_area = signed_map_size * signed_map_size;
-        let abs_x = signed_map_size + local_pos.x;
-        let abs_y = signed_map_size - local_pos.y;
-        assert!(abs_x >= 0);
-        assert!(abs_y >= 0);
-        assert!(local_pos.z >= 0);
-        return (local_pos.z * signed_layer_area + abs_y * signed_map_size + abs_x) as usize
+    pub fn set_light(&mut self, kind: LightKind, light: Option<LightArray>) {
+        match kind {
+            LightKind::Sky => { self.sky_light = light; }
+            LightKind::Block => { self.block_light = light; }
+        }
+        self.dirty = true;
+        self.revision = next_revision();
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        let light = [&self.sky_light, &self.block_light].iter().filter(|l| l.is_some()).count() * light::LIGHT_ARRAY_BYTES;
+        return std::mem::size_of::<SubChunk>() + self.blocks.memory_usage() + light;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.blocks.single_value() == Some(&block::Block::AIR);
+    }
+
+    // The sub-chunk doesn't know where it is, so the caller passes its position.
+    pub fn blocks(&self, pos: SubChunkPos) -> impl Iterator<Item = (BlockPos, &block::Block)> {
+        return (0..LocalPos::VOLUME).map(move |idx| (pos.block(LocalPos::from_index(idx)), self.blocks.get(idx)));
+    }
+
+    // Set on every change, cleared by whoever persists the sub-chunk.
+    pub fn is_dirty(&self) -> bool {
+        return self.dirty;
     }
 
-    pub fn block(&self, local_pos: Vector3<PosScalar>) -> Option<&block::Block> {
-        return Some(&self.blocks[SubChunk::pos_to_idx(local_pos)]);
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    // Revision of the last change, 0 if never changed.
+    pub fn revision(&self) -> u64 {
+        return self.revision;
     }
 }
 
 impl Chunk {
-    pub fn generate(pos: Vector2<PosScalar>) -> Self {
-        let sub_chunks = [SubChunk::empty(); SUBCHUNK_COUNT];
-        return Chunk{pos, sub_chunks};
+    pub fn empty(pos: ChunkPos) -> Self {
+        let sub_chunks = (0..SUBCHUNK_COUNT).map(|_| None).collect();
+        let heightmaps = HeightmapKind::ALL.iter().map(|kind| Heightmap::new(*kind)).collect();
+        return Chunk{pos, sub_chunks, block_entities: BlockEntityStore::new(), block_entities_dirty: false, heightmaps,
+            biomes: BiomeMap::new(Biome::DEFAULT), biomes_dirty: false};
+    }
+
+    // Generated chunks aren't dirty, the same seed and generator settings produce them again.
+    pub fn generate(pos: ChunkPos, seed: u64, generator: &dyn WorldGenerator) -> Self {
+        let mut chunk = Chunk::empty(pos);
+        generator.generate(seed, &mut chunk);
+        chunk.clear_dirty();
+        return chunk;
+    }
+
+    pub fn pos(&self) -> ChunkPos {
+        return self.pos;
+    }
+
+    pub fn contains(&self, pos: BlockPos) -> bool {
+        return pos.chunk() == self.pos && pos.z >= 0 && pos.z < SUBCHUNK_COUNT as PosScalar * config::SUBCHUNK_SIZE as PosScalar;
+    }
+
+    pub fn bounds(&self) -> BlockBox {
+        return self.pos.column(0, SUBCHUNK_COUNT as PosScalar * config::SUBCHUNK_SIZE as PosScalar - 1);
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (BlockPos, &block::Block)> {
+        return self.blocks_in(self.bounds());
+    }
+
+    // Blocks of this chunk that are inside of the box, missing sub-chunks yield air.
+    pub fn blocks_in(&self, bounds: BlockBox) -> impl Iterator<Item = (BlockPos, &block::Block)> {
+        return self.bounds().intersection(&bounds).into_iter()
+            .flat_map(|clipped| clipped.positions())
+            .map(move |pos| (pos, self.block(pos).unwrap()));
     }
 
-    fn pos_to_idx(pos: Vector3<PosScalar>) -> usize {
-        return (pos.z as SizeScalar / config::SUBCHUNK_SIZE) as usize;
+    // Mutable counterpart of blocks_in, returning Some from the closure replaces the block.
+    pub fn update_blocks_in<F>(&mut self, bounds: BlockBox, registry: &BlockRegistry, mut f: F)
+        where F: FnMut(BlockPos, &block::Block) -> Option<block::Block> {
+        let clipped = match self.bounds().intersection(&bounds) {
+            Some(clipped) => clipped,
+            None => { return; }
+        };
+        for pos in clipped.positions() {
+            let old = *self.block(pos).unwrap();
+            if let Some(new) = f(pos, &old) {
+                self.set_block(pos, new, registry);
+            }
+        }
+    }
+
+    fn subchunk_index(&self, pos: SubChunkPos) -> Option<usize> {
+        if pos.chunk() != self.pos || pos.z < 0 || pos.z as usize >= SUBCHUNK_COUNT {
+            return None;
+        }
+        return Some(pos.z as usize);
+    }
+
+    // None for sub-chunks that are all air and were never allocated.
+    pub fn subchunk(&self, pos: SubChunkPos) -> Option<&SubChunk> {
+        return self.sub_chunks[self.subchunk_index(pos)?].as_deref();
+    }
+
+    pub fn subchunk_mut(&mut self, pos: SubChunkPos) -> Option<&mut SubChunk> {
+        let idx = self.subchunk_index(pos)?;
+        return self.sub_chunks[idx].as_deref_mut();
+    }
+
+    // Replaces the sub-chunk at the given position, e.g. when loading from disk.
+    pub fn set_subchunk(&mut self, pos: SubChunkPos, subchunk: SubChunk) {
+        if let Some(idx) = self.subchunk_index(pos) {
+            self.sub_chunks[idx] = Some(Box::new(subchunk));
+        }
+    }
+
+    pub fn allocated_subchunks(&self) -> impl Iterator<Item = (SubChunkPos, &SubChunk)> {
+        return self.sub_chunks.iter().enumerate()
+            .filter_map(move |(idx, s)| s.as_deref().map(|s| (self.pos.subchunk(idx as PosScalar), s)));
+    }
+
+    pub fn is_subchunk_empty(&self, pos: SubChunkPos) -> bool {
+        return self.subchunk(pos).is_none_or(|s| s.is_empty());
+    }
+
+    fn subchunks(&self) -> impl Iterator<Item = &SubChunk> {
+        return self.sub_chunks.iter().filter_map(|s| s.as_deref());
+    }
+
+    // Frees sub-chunks that became all air, dirty ones are kept until they are saved.
+    pub fn release_empty_subchunks(&mut self) {
+        for slot in self.sub_chunks.iter_mut() {
+            if slot.as_ref().is_some_and(|s| s.is_empty() && !s.is_dirty()) {
+                *slot = None;
+            }
+        }
+    }
+
+    // Approximate memory used by the chunk, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        return std::mem::size_of::<Chunk>()
+            + self.sub_chunks.capacity() * std::mem::size_of::<Option<Box<SubChunk>>>()
+            + self.subchunks().map(|s| s.memory_usage()).sum::<usize>()
+            + std::mem::size_of_val(self.biomes.biomes());
+    }
+
+    // True if there is nothing but air in the chunk.
+    pub fn is_empty(&self) -> bool {
+        return self.block_entities.is_empty() && self.subchunks().all(|s| s.is_empty());
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        return self.block_entities_dirty || self.biomes_dirty || self.subchunks().any(|s| s.is_dirty());
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.block_entities_dirty = false;
+        self.biomes_dirty = false;
+        for subchunk in self.sub_chunks.iter_mut().filter_map(|s| s.as_deref_mut()) {
+            subchunk.clear_dirty();
+        }
     }
 
-    fn local_subchunk_pos(pos: Vector3<PosScalar>) -> Vector3<PosScalar> {
-        let signed_size = config::SUBCHUNK_SIZE as i32;
-        return Vector3::<PosScalar>::new(pos.x % signed_size, pos.y % signed_size, pos.z % signed_size);
+    pub fn changed_since(&self, revision: u64) -> impl Iterator<Item = SubChunkPos> + '_ {
+        return self.sub_chunks.iter().enumerate()
+            .filter(move |(_, s)| s.as_ref().is_some_and(|s| s.revision() > revision))
+            .map(move |(idx, _)| self.pos.subchunk(idx as PosScalar));
     }
 
-    pub fn subchunk(&self, pos: Vector3<PosScalar>) -> Option<&SubChunk> {
-        return Some(&self.sub_chunks[Chunk::pos_to_idx(pos)]);
+    pub fn block(&self, pos: BlockPos) -> Option<&block::Block> {
+        let idx = self.subchunk_index(pos.subchunk())?;
+        match self.sub_chunks[idx].as_deref() {
+            Some(subchunk) => { return subchunk.block(pos.local()); }
+            None => { return Some(&block::Block::AIR); }
+        }
     }
 
-    pub fn block(&self, pos: Vector3<PosScalar>) -> Option<&block::Block> {
-        match self.subchunk(pos) {
-            Some(subchunk) => { return subchunk.block(Chunk::local_subchunk_pos(pos)); }
-            None => { return None; }
+    // Replacing a block with one of a different type drops its block entity,
+    // and creates a fresh one if the new block needs it.
+    // Visibility of the block and its neighbours is updated, neighbours outside
+    // of this chunk are treated as transparent, the Map fixes up chunk borders.
+    pub fn set_block(&mut self, pos: BlockPos, block: block::Block, registry: &BlockRegistry) {
+        let old_id = match self.block(pos) {
+            Some(old) => old.block_id(),
+            None => { return; }
+        };
+        self.set_raw_block(pos, block);
+
+        if old_id != block.block_id() {
+            self.update_heightmaps(pos, registry);
+            let removed = self.block_entities.remove(pos).is_some();
+            let created = match registry.properties(&block).block_entity {
+                Some(kind) => { self.block_entities.insert(pos, BlockEntity::create(kind)); true }
+                None => false,
+            };
+            self.block_entities_dirty |= removed || created;
+        }
+
+        self.refresh_visibility(pos, registry);
+        for face in block::Face::ALL.iter() {
+            let neighbour = pos + face.offset();
+            if self.contains(neighbour) {
+                self.refresh_visibility(neighbour, registry);
+            }
+        }
+    }
+
+    fn set_raw_block(&mut self, pos: BlockPos, block: block::Block) {
+        let slot = &mut self.sub_chunks[pos.subchunk().z as usize];
+        if slot.is_none() {
+            if block == block::Block::AIR {
+                return;
+            }
+            *slot = Some(Box::new(SubChunk::empty()));
+        }
+        slot.as_mut().unwrap().set_block(pos.local(), block);
+    }
+
+    // Blocks of missing sub-chunks are air, which has no visible faces.
+    pub(crate) fn set_visible_mask(&mut self, pos: BlockPos, mask: u8) {
+        if !self.contains(pos) {
+            return;
+        }
+        if let Some(subchunk) = self.sub_chunks[pos.subchunk().z as usize].as_mut() {
+            subchunk.set_visible_mask(pos.local(), mask);
+        }
+    }
+
+    fn is_opaque(&self, pos: BlockPos, registry: &BlockRegistry) -> bool {
+        if !self.contains(pos) {
+            return false;
+        }
+        return registry.properties(self.block(pos).unwrap()).opaque;
+    }
+
+    fn refresh_visibility(&mut self, pos: BlockPos, registry: &BlockRegistry) {
+        let block = *self.block(pos).unwrap();
+        let mask = block::visible_faces(&block, |face| self.is_opaque(pos + face.offset(), registry));
+        self.set_visible_mask(pos, mask);
+    }
+
+    // Recomputes the whole chunk, e.g. after generation.
+    pub fn refresh_all_visibility(&mut self, registry: &BlockRegistry) {
+        for index in 0..SUBCHUNK_COUNT {
+            if self.sub_chunks[index].is_none() {
+                continue;
+            }
+            let subchunk_pos = self.pos.subchunk(index as PosScalar);
+            for local_index in 0..LocalPos::VOLUME {
+                self.refresh_visibility(subchunk_pos.block(LocalPos::from_index(local_index)), registry);
+            }
+        }
+    }
+
+    pub fn heightmap(&self, kind: HeightmapKind) -> &Heightmap {
+        return &self.heightmaps[kind.index()];
+    }
+
+    pub fn heightmaps(&self) -> &[Heightmap] {
+        return &self.heightmaps;
+    }
+
+    // Used when loading, heightmaps of kinds that aren't given are recomputed.
+    pub fn set_heightmaps(&mut self, heightmaps: Vec<Heightmap>, registry: &BlockRegistry) {
+        for kind in HeightmapKind::ALL.iter() {
+            match heightmaps.iter().find(|h| h.kind() == *kind) {
+                Some(heightmap) => { self.heightmaps[kind.index()] = heightmap.clone(); }
+                None => { self.recompute_heightmap(*kind, registry); }
+            }
+        }
+    }
+
+    // Column height in world coordinates, None outside of the chunk.
+    pub fn height(&self, kind: HeightmapKind, x: PosScalar, y: PosScalar) -> Option<PosScalar> {
+        let pos = BlockPos::new(x, y, 0);
+        if pos.chunk() != self.pos {
+            return None;
+        }
+        let local = pos.local();
+        return Some(self.heightmap(kind).height(local.x, local.y));
+    }
+
+    pub fn biomes(&self) -> &BiomeMap {
+        return &self.biomes;
+    }
+
+    pub fn set_biomes(&mut self, biomes: BiomeMap) {
+        self.biomes = biomes;
+        self.biomes_dirty = true;
+    }
+
+    // Column biome in world coordinates, None outside of the chunk.
+    pub fn biome(&self, x: PosScalar, y: PosScalar) -> Option<Biome> {
+        let pos = BlockPos::new(x, y, 0);
+        if pos.chunk() != self.pos {
+            return None;
+        }
+        let local = pos.local();
+        return Some(self.biomes.biome(local.x, local.y));
+    }
+
+    pub fn set_biome(&mut self, x: PosScalar, y: PosScalar, biome: Biome) {
+        let pos = BlockPos::new(x, y, 0);
+        if pos.chunk() == self.pos {
+            let local = pos.local();
+            self.biomes.set_biome(local.x, local.y, biome);
+            self.biomes_dirty = true;
+        }
+    }
+
+    // Only called when the block type changed, the state doesn't affect any of the heightmaps.
+    fn update_heightmaps(&mut self, pos: BlockPos, registry: &BlockRegistry) {
+        let local = pos.local();
+        let block = *self.block(pos).unwrap();
+        for kind in HeightmapKind::ALL.iter() {
+            let height = self.heightmaps[kind.index()].height(local.x, local.y);
+            let new_height = if kind.matches(&block, registry) {
+                height.max(pos.z + 1)
+            } else if pos.z + 1 == height {
+                self.find_height(pos, *kind, registry)
+            } else {
+                height
+            };
+            self.heightmaps[kind.index()].set_height(local.x, local.y, new_height);
+        }
+    }
+
+    // Scans the column downward starting below `top`, skipping unallocated sub-chunks.
+    fn find_height(&self, top: BlockPos, kind: HeightmapKind, registry: &BlockRegistry) -> PosScalar {
+        let size = config::SUBCHUNK_SIZE as PosScalar;
+        let mut pos = top;
+        while pos.z > 0 {
+            pos.z -= 1;
+            if self.sub_chunks[(pos.z / size) as usize].is_none() {
+                pos.z -= pos.z % size;
+                continue;
+            }
+            if kind.matches(self.block(pos).unwrap(), registry) {
+                return pos.z + 1;
+            }
+        }
+        return 0;
+    }
+
+    fn recompute_heightmap(&mut self, kind: HeightmapKind, registry: &BlockRegistry) {
+        let origin = self.pos.origin();
+        let top = SUBCHUNK_COUNT as PosScalar * config::SUBCHUNK_SIZE as PosScalar;
+        for y in 0..config::SUBCHUNK_SIZE as u8 {
+            for x in 0..config::SUBCHUNK_SIZE as u8 {
+                let column_top = BlockPos::new(origin.x + x as PosScalar, origin.y + y as PosScalar, top);
+                let height = self.find_height(column_top, kind, registry);
+                self.heightmaps[kind.index()].set_height(x, y, height);
+            }
+        }
+    }
+
+    // Recomputes all heightmaps, e.g. after generation.
+    pub fn recompute_heightmaps(&mut self, registry: &BlockRegistry) {
+        for kind in HeightmapKind::ALL.iter() {
+            self.recompute_heightmap(*kind, registry);
+        }
+    }
+
+    pub fn block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        return self.block_entities.get(pos);
+    }
+
+    // Marks the chunk dirty, since the caller may change the entity.
+    pub fn block_entity_mut(&mut self, pos: BlockPos) -> Option<&mut BlockEntity> {
+        let entity = self.block_entities.get_mut(pos);
+        self.block_entities_dirty |= entity.is_some();
+        return entity;
+    }
+
+    pub fn block_entities(&self) -> &BlockEntityStore {
+        return &self.block_entities;
+    }
+
+    pub fn set_block_entities(&mut self, block_entities: BlockEntityStore) {
+        self.block_entities = block_entities;
+    }
+
+    // Only entities that changed, like a burning furnace, make the chunk dirty.
+    pub fn tick_block_entities(&mut self, dt: std::time::Duration) {
+        if self.block_entities.tick(dt) {
+            self.block_entities_dirty = true;
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block::Face;
+    use crate::model::block_entity::BlockEntityKind;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn blocks_round_trip_in_every_quadrant() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        for (cx, cy) in [(0, 0), (-1, 0), (0, -1), (-1, -1), (3, -5), (-7, 2)].iter() {
+            let mut chunk = Chunk::empty(ChunkPos::new(*cx, *cy));
+            let origin = chunk.pos().origin();
+            let corners = [
+                origin,
+                origin + Vector3::new(15, 0, 17),
+                origin + Vector3::new(0, 15, 100),
+                origin + Vector3::new(15, 15, 255),
+            ];
+            for pos in corners.iter() {
+                assert!(chunk.block(*pos).unwrap().is_air());
+                chunk.set_block(*pos, stone, &registry);
+                assert_eq!(chunk.block(*pos).unwrap().block_id(), stone.block_id());
+            }
+            assert!(chunk.block(origin + Vector3::new(1, 0, 0)).unwrap().is_air());
+            assert!(chunk.block(origin + Vector3::new(-1, 0, 0)).is_none());
+            assert!(chunk.block(origin + Vector3::new(0, 0, -1)).is_none());
+            assert!(chunk.block(origin + Vector3::new(0, 0, 256)).is_none());
+        }
+    }
+
+    #[test]
+    fn neighbours_hide_faces() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let glass = registry.block("glass").unwrap();
+        let mut chunk = Chunk::empty(ChunkPos::new(-1, -1));
+        let pos = chunk.pos().origin() + Vector3::new(4, 15, 16);
+        let above = pos + Face::Up.offset();
+
+        chunk.set_block(pos, stone, &registry);
+        assert_eq!(chunk.block(pos).unwrap().visible_mask(), 0b111111);
+
+        chunk.set_block(above, stone, &registry);
+        assert!(!chunk.block(pos).unwrap().is_face_visible(Face::Up));
+        assert!(!chunk.block(above).unwrap().is_face_visible(Face::Down));
+
+        chunk.set_block(above, glass, &registry);
+        assert!(chunk.block(pos).unwrap().is_face_visible(Face::Up));
+        assert!(!chunk.block(above).unwrap().is_face_visible(Face::Down));
+
+        chunk.set_block(pos, block::Block::new(), &registry);
+        assert_eq!(chunk.block(pos).unwrap().visible_mask(), 0);
+        assert!(chunk.block(above).unwrap().is_face_visible(Face::Down));
+    }
+
+    #[test]
+    fn palette_storage_saves_memory() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let dirt = registry.block("dirt").unwrap();
+        let unpacked_chunk = SUBCHUNK_COUNT * LocalPos::VOLUME * std::mem::size_of::<block::Block>();
+
+        let mut chunk = Chunk::empty(ChunkPos::new(0, 0));
+        assert!(chunk.memory_usage() < unpacked_chunk / 100);
+
+        // Two layers of terrain in the first sub-chunk.
+        let origin = chunk.pos().origin();
+        for y in 0..16 {
+            for x in 0..16 {
+                chunk.set_block(origin + Vector3::new(x, y, 0), stone, &registry);
+                chunk.set_block(origin + Vector3::new(x, y, 1), dirt, &registry);
+            }
+        }
+        assert_eq!(chunk.block(origin + Vector3::new(3, 3, 1)).unwrap().block_id(), dirt.block_id());
+        assert!(chunk.memory_usage() < unpacked_chunk / 32);
+    }
+
+    #[test]
+    fn subchunks_are_allocated_lazily() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let mut chunk = Chunk::empty(ChunkPos::new(-2, 5));
+        let pos = chunk.pos().origin() + Vector3::new(7, 7, 70);
+        let empty_usage = chunk.memory_usage();
+
+        for idx in 0..SUBCHUNK_COUNT as PosScalar {
+            assert!(chunk.subchunk(chunk.pos().subchunk(idx)).is_none());
+            assert!(chunk.is_subchunk_empty(chunk.pos().subchunk(idx)));
+        }
+        assert!(chunk.block(pos).unwrap().is_air());
+
+        // Writing air doesn't allocate.
+        chunk.set_block(pos, block::Block::AIR, &registry);
+        assert!(chunk.subchunk(pos.subchunk()).is_none());
+        assert!(!chunk.is_dirty());
+
+        chunk.set_block(pos, stone, &registry);
+        assert!(chunk.subchunk(pos.subchunk()).is_some());
+        assert!(!chunk.is_subchunk_empty(pos.subchunk()));
+        assert!(chunk.memory_usage() > empty_usage);
+        assert_eq!(chunk.subchunks().count(), 1);
+
+        chunk.set_block(pos, block::Block::AIR, &registry);
+        assert!(chunk.is_subchunk_empty(pos.subchunk()));
+        chunk.release_empty_subchunks();
+        assert!(chunk.subchunk(pos.subchunk()).is_some());
+        chunk.clear_dirty();
+        chunk.release_empty_subchunks();
+        assert!(chunk.subchunk(pos.subchunk()).is_none());
+        assert_eq!(chunk.memory_usage(), empty_usage);
+    }
+
+    #[test]
+    fn iterates_over_blocks() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let mut chunk = Chunk::empty(ChunkPos::new(-1, 1));
+        assert_eq!(chunk.blocks().count(), SUBCHUNK_COUNT * LocalPos::VOLUME);
+
+        let origin = chunk.pos().origin();
+        let floor = BlockBox::new(origin + Vector3::new(-5, 0, 0), origin + Vector3::new(3, 20, 1));
+        chunk.update_blocks_in(floor, &registry, |_, _| Some(stone));
+        let stones: Vec<_> = chunk.blocks().filter(|(_, b)| !b.is_air()).map(|(p, _)| p).collect();
+        assert_eq!(stones.len(), 4 * 16 * 2);
+        assert!(stones.iter().all(|p| floor.contains(*p) && chunk.contains(*p)));
+
+        let subchunk_pos = chunk.pos().subchunk(0);
+        let subchunk = chunk.subchunk(subchunk_pos).unwrap();
+        assert_eq!(subchunk.blocks(subchunk_pos).filter(|(_, b)| !b.is_air()).count(), stones.len());
+        assert!(subchunk.blocks(subchunk_pos).all(|(p, b)| chunk.block(p) == Some(b)));
+    }
+
+    #[test]
+    fn heightmaps_follow_blocks() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let glass = registry.block("glass").unwrap();
+        let water = registry.block("water").unwrap();
+        let wheat = registry.block("wheat").unwrap();
+        let mut chunk = Chunk::empty(ChunkPos::new(-3, 2));
+        let column = chunk.pos().origin() + Vector3::new(5, 9, 0);
+        let height = |chunk: &Chunk, kind| chunk.height(kind, column.x, column.y).unwrap();
+        assert!(HeightmapKind::ALL.iter().all(|kind| height(&chunk, *kind) == 0));
+
+        chunk.set_block(column + Vector3::new(0, 0, 3), stone, &registry);
+        chunk.set_block(column + Vector3::new(0, 0, 40), glass, &registry);
+        chunk.set_block(column + Vector3::new(0, 0, 41), water, &registry);
+        chunk.set_block(column + Vector3::new(0, 0, 42), wheat, &registry);
+        assert_eq!(height(&chunk, HeightmapKind::WorldSurface), 43);
+        assert_eq!(height(&chunk, HeightmapKind::MotionBlocking), 42);
+        assert_eq!(height(&chunk, HeightmapKind::Solid), 41);
+        assert_eq!(height(&chunk, HeightmapKind::Opaque), 4);
+
+        // Removing the top blocks scans down, across the empty sub-chunks in between.
+        chunk.set_block(column + Vector3::new(0, 0, 40), block::Block::AIR, &registry);
+        chunk.set_block(column + Vector3::new(0, 0, 41), block::Block::AIR, &registry);
+        assert_eq!(height(&chunk, HeightmapKind::MotionBlocking), 4);
+        assert_eq!(height(&chunk, HeightmapKind::WorldSurface), 43);
+        assert_eq!(chunk.height(HeightmapKind::Solid, column.x + 16, column.y), None);
+
+        let mut recomputed = chunk.clone();
+        recomputed.recompute_heightmaps(&registry);
+        assert_eq!(recomputed.heightmaps(), chunk.heightmaps());
+
+        let serialized = ron::ser::to_string(&chunk.heightmaps()).unwrap();
+        let mut loaded = Chunk::empty(chunk.pos());
+        loaded.set_heightmaps(ron::de::from_str(&serialized).unwrap(), &registry);
+        assert_eq!(loaded.heightmaps(), chunk.heightmaps());
+    }
+
+    #[test]
+    fn changes_are_tracked() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let mut chunk = Chunk::empty(ChunkPos::new(0, -1));
+        let pos = chunk.pos().origin() + Vector3::new(1, 2, 40);
+        assert!(!chunk.is_dirty());
+
+        let before = current_revision();
+        chunk.set_block(pos, stone, &registry);
+        assert!(chunk.is_dirty());
+        assert_eq!(chunk.changed_since(before).collect::<Vec<_>>(), vec![pos.subchunk()]);
+
+        let after = current_revision();
+        chunk.clear_dirty();
+        assert!(!chunk.is_dirty());
+        assert_eq!(chunk.changed_since(after).count(), 0);
+        assert_eq!(chunk.changed_since(before).count(), 1);
+
+        // Writing the same block again is not a change.
+        chunk.set_block(pos, *chunk.block(pos).unwrap(), &registry);
+        assert!(!chunk.is_dirty());
+        assert_eq!(chunk.changed_since(after).count(), 0);
+
+        // Visibility updates of neighbours in the sub-chunk below count as changes too.
+        let below = chunk.pos().origin() + Vector3::new(1, 2, 31);
+        chunk.set_block(below, stone, &registry);
+        let after_below = current_revision();
+        chunk.set_block(below + Vector3::new(0, 0, 1), stone, &registry);
+        let changed: Vec<_> = chunk.changed_since(after_below).collect();
+        assert_eq!(changed, vec![below.subchunk(), pos.subchunk()]);
+
+        // So do light updates.
+        let after_blocks = current_revision();
+        chunk.subchunk_mut(pos.subchunk()).unwrap().set_light(LightKind::Sky, Some(LightArray::new(15)));
+        assert_eq!(chunk.changed_since(after_blocks).collect::<Vec<_>>(), vec![pos.subchunk()]);
+
+        chunk.clear_dirty();
+        chunk.set_biome(pos.x, pos.y, Biome::Desert);
+        assert!(chunk.is_dirty());
+        assert_eq!(chunk.biome(pos.x, pos.y), Some(Biome::Desert));
+        assert_eq!(chunk.biome(pos.x, pos.y + 1), Some(Biome::DEFAULT));
+        assert_eq!(chunk.biome(pos.x, pos.y + 16), None);
+    }
+
+    #[test]
+    fn block_entities_follow_blocks() {
+        let registry = BlockRegistry::builtin();
+        let chest = registry.block("chest").unwrap();
+        let mut chunk = Chunk::empty(ChunkPos::new(2, -3));
+        let pos = chunk.pos().origin() + Vector3::new(3, 4, 5);
+
+        chunk.set_block(pos, chest, &registry);
+        assert_eq!(chunk.block_entity(pos).unwrap().kind(), BlockEntityKind::Chest);
+
+        // Changing only the state keeps the contents.
+        chunk.set_block(pos, chest.with(&registry, "facing", "east").unwrap(), &registry);
+        assert!(chunk.block_entity(pos).is_some());
+
+        // Chests don't change on their own
+        chunk.clear_dirty();
+        chunk.tick_block_entities(std::time::Duration::from_secs(1));
+        assert!(!chunk.is_dirty());
+
+        chunk.set_block(pos, registry.block("stone").unwrap(), &registry);
+        assert!(chunk.block_entity(pos).is_none());
+        assert!(chunk.block_entities().is_empty());
+    }
+}
@@ -1,8 +1,9 @@
 use crate::core::*;
 
-pub const SUBCHUNK_SIZE:  SizeScalar = 16;
+pub use crate::core::SUBCHUNK_SIZE;
 
 pub const BUILD_LIMIT: SizeScalar = 16; // In chunks
-pub const MAP_SIZE:  SizeScalar = 32; // In chunks, block radius
+pub const VIEW_RADIUS: SizeScalar = 8; // In chunks, around every player
+pub const CHUNK_WORKER_THREADS: usize = 2; // Loading and generating chunks
 
 
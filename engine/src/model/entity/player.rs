@@ -0,0 +1,44 @@
+use crate::core::*;
+use crate::model::block_entity::ItemStack;
+
+use serde::{Deserialize, Serialize};
+
+pub const INVENTORY_SLOTS: usize = 36;
+pub const MAX_HEALTH: f32 = 20.0;
+
+// Persistent state of a player, stored per player in the world directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerData {
+    pub name: String,
+    pub position: [f64; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub health: f32,
+    pub inventory: Vec<Option<ItemStack>>,
+    pub selected_slot: u8,
+}
+
+impl PlayerData {
+    pub fn new(name: &str, position: [f64; 3]) -> Self {
+        return PlayerData{
+            name: name.to_string(),
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            health: MAX_HEALTH,
+            inventory: vec![None; INVENTORY_SLOTS],
+            selected_slot: 0,
+        };
+    }
+
+    pub fn block_pos(&self) -> BlockPos {
+        let [x, y, z] = self.position;
+        return BlockPos::new(x.floor() as PosScalar, y.floor() as PosScalar, z.floor() as PosScalar);
+    }
+
+    // Names are used as file names, so only a safe subset of characters is allowed.
+    pub fn is_valid_name(name: &str) -> bool {
+        return !name.is_empty() && name.len() <= 32
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    }
+}
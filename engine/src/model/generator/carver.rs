@@ -0,0 +1,286 @@
+use crate::core::*;
+use crate::model::level::GeneratorSettings;
+use crate::util::noise::{derive_seed, Fractal, FractalKind, Noise, SeedRng, Simplex};
+use super::{parse_option, GeneratorError};
+
+const CHEESE_CAVES_OPTION: &str = "cheese_caves";
+const TUNNELS_OPTION: &str = "tunnels";
+const RAVINES_OPTION: &str = "ravines";
+
+// Salts of the noise sources and feature placement, changing one changes the caves of every seed
+const CHEESE_SALT: u64 = 8;
+const TUNNELS_SALT: u64 = 9;
+const RAVINES_SALT: u64 = 10;
+
+// Cheese caves are where the noise is above the threshold, at least SURFACE_DEPTH blocks below
+// the ground and above the bedrock floor
+const CHEESE_THRESHOLD: f64 = 0.4;
+const SURFACE_DEPTH: PosScalar = 8;
+const MIN_Z: PosScalar = 4;
+
+// One in TUNNEL_CHANCE chunks starts a few tunnels, one in RAVINE_CHANCE a ravine
+const TUNNEL_CHANCE: u64 = 3;
+const RAVINE_CHANCE: u64 = 40;
+const MAX_LENGTH: PosScalar = 72;
+const MAX_RADIUS: f64 = 3.5;
+// Ravines are this many times deeper than they are wide
+const RAVINE_DEPTH: f64 = 3.5;
+// In chunks, tunnels and ravines never reach further than this from the chunk they start in:
+// MAX_LENGTH + MAX_RADIUS blocks from anywhere in the chunk, rounded up
+const RANGE: PosScalar = 6;
+
+// Which carvers run, each of them can be turned off in the options of the generator.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CarverOptions {
+    pub cheese_caves: bool,
+    pub tunnels: bool,
+    pub ravines: bool,
+}
+
+impl CarverOptions {
+    pub fn from_settings(settings: &GeneratorSettings) -> Result<Self, GeneratorError> {
+        return Ok(CarverOptions{
+            cheese_caves: parse_option(settings, CHEESE_CAVES_OPTION, true)?,
+            tunnels: parse_option(settings, TUNNELS_OPTION, true)?,
+            ravines: parse_option(settings, RAVINES_OPTION, true)?,
+        });
+    }
+}
+
+// A tunnel or ravine, walked one block at a time. Directions are kept normalized with sqrt and the
+// width follows a parabola, so like the noise no transcendental functions are involved.
+struct Worm {
+    // Of the random turns
+    seed: u64,
+    pos: [f64; 3],
+    direction: [f64; 3],
+    length: PosScalar,
+    radius: f64,
+    // Of the vertical radius to the horizontal one
+    depth: f64,
+    // Largest random change of the direction per block, horizontal and vertical
+    turn: f64,
+    climb: f64,
+}
+
+// Hollows out underground spaces in freshly generated terrain. What is carved is a function of the
+// seed and the position only, tunnels and ravines starting in a chunk are carved into every chunk
+// they pass through.
+pub struct Carvers {
+    options: CarverOptions,
+    seed: u64,
+    cheese: Fractal<Simplex>,
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length == 0.0 {
+        return [1.0, 0.0, 0.0];
+    }
+    return [v[0] / length, v[1] / length, v[2] / length];
+}
+
+// In [-1, 1).
+fn signed(rng: &mut SeedRng) -> f64 {
+    return rng.next_f64() * 2.0 - 1.0;
+}
+
+impl Carvers {
+    pub fn new(seed: u64, options: CarverOptions) -> Self {
+        let mut cheese = Fractal::new(FractalKind::Fbm, 2, derive_seed(seed, CHEESE_SALT), Simplex::new);
+        cheese.frequency = 1.0 / 40.0;
+        return Carvers{ options, seed, cheese };
+    }
+
+    // Calls carve with the positions of the area that are carved out, possibly more than once.
+    // ground is the height of the terrain of a column. Whether a block can be carved, e.g. next
+    // to water, is up to the caller.
+    pub fn carve<G: Fn(PosScalar, PosScalar) -> PosScalar, C: FnMut(BlockPos)>(&self, area: BlockBox, ground: G, mut carve: C) {
+        if self.options.cheese_caves {
+            self.carve_cheese(area, &ground, &mut carve);
+        }
+        let (min, max) = (area.min.chunk(), area.max.chunk());
+        for y in min.y - RANGE..=max.y + RANGE {
+            for x in min.x - RANGE..=max.x + RANGE {
+                let chunk = ChunkPos::new(x, y);
+                if self.options.tunnels {
+                    for worm in self.tunnels(chunk) {
+                        carve_worm(worm, area, &mut carve);
+                    }
+                }
+                if self.options.ravines {
+                    if let Some(worm) = self.ravine(chunk) {
+                        carve_worm(worm, area, &mut carve);
+                    }
+                }
+            }
+        }
+    }
+
+    fn carve_cheese<G: Fn(PosScalar, PosScalar) -> PosScalar, C: FnMut(BlockPos)>(&self, area: BlockBox, ground: &G, carve: &mut C) {
+        for y in area.min.y..=area.max.y {
+            for x in area.min.x..=area.max.x {
+                let top = (ground(x, y) - SURFACE_DEPTH).min(area.max.z + 1);
+                for z in area.min.z.max(MIN_Z)..top {
+                    // Squashed vertically, caverns are wider than they are high
+                    if self.cheese.sample3(x as f64, y as f64, z as f64 * 2.0) > CHEESE_THRESHOLD {
+                        carve(BlockPos::new(x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    fn rng(&self, salt: u64, chunk: ChunkPos) -> SeedRng {
+        let key = ((chunk.x as u32 as u64) << 32) | chunk.y as u32 as u64;
+        return SeedRng::new(derive_seed(derive_seed(self.seed, salt), key));
+    }
+
+    fn tunnels(&self, chunk: ChunkPos) -> Vec<Worm> {
+        let mut rng = self.rng(TUNNELS_SALT, chunk);
+        if rng.next_below(TUNNEL_CHANCE) != 0 {
+            return Vec::new();
+        }
+        let origin = chunk.origin();
+        let count = 1 + rng.next_below(3);
+        return (0..count).map(|_| Worm{
+            seed: rng.next_u64(),
+            pos: [
+                origin.x as f64 + rng.next_f64() * 16.0,
+                origin.y as f64 + rng.next_f64() * 16.0,
+                8.0 + rng.next_f64() * 72.0,
+            ],
+            direction: normalize([signed(&mut rng), signed(&mut rng), signed(&mut rng) * 0.25]),
+            length: MAX_LENGTH - 32 + rng.next_below(33) as PosScalar,
+            radius: 1.5 + rng.next_f64() * (MAX_RADIUS - 1.5),
+            depth: 1.0,
+            turn: 0.3,
+            climb: 0.1,
+        }).collect();
+    }
+
+    fn ravine(&self, chunk: ChunkPos) -> Option<Worm> {
+        let mut rng = self.rng(RAVINES_SALT, chunk);
+        if rng.next_below(RAVINE_CHANCE) != 0 {
+            return None;
+        }
+        let origin = chunk.origin();
+        return Some(Worm{
+            seed: rng.next_u64(),
+            pos: [
+                origin.x as f64 + rng.next_f64() * 16.0,
+                origin.y as f64 + rng.next_f64() * 16.0,
+                20.0 + rng.next_f64() * 40.0,
+            ],
+            direction: normalize([signed(&mut rng), signed(&mut rng), 0.0]),
+            length: MAX_LENGTH - 16 + rng.next_below(17) as PosScalar,
+            radius: 2.0 + rng.next_f64(),
+            depth: RAVINE_DEPTH,
+            turn: 0.1,
+            climb: 0.02,
+        });
+    }
+}
+
+// Walks the whole worm whatever the area, so the random turns are the same for every chunk.
+fn carve_worm<C: FnMut(BlockPos)>(mut worm: Worm, area: BlockBox, carve: &mut C) {
+    let mut rng = SeedRng::new(worm.seed);
+    for step in 0..worm.length {
+        // Narrow at both ends, widest in the middle
+        let t = step as f64 / worm.length as f64 * 2.0 - 1.0;
+        let radius = 1.0 + (worm.radius - 1.0) * (1.0 - t * t);
+        carve_ellipsoid(worm.pos, radius, radius * worm.depth, area, carve);
+
+        let d = worm.direction;
+        worm.direction = normalize([
+            d[0] + signed(&mut rng) * worm.turn,
+            d[1] + signed(&mut rng) * worm.turn,
+            // Tunnels level out instead of diving to the bedrock
+            d[2] * 0.7 + signed(&mut rng) * worm.climb,
+        ]);
+        for (p, d) in worm.pos.iter_mut().zip(worm.direction.iter()) {
+            *p += d;
+        }
+    }
+}
+
+fn carve_ellipsoid<C: FnMut(BlockPos)>(center: [f64; 3], radius: f64, vertical: f64, area: BlockBox, carve: &mut C) {
+    let bounds = BlockBox::new(
+        BlockPos::new((center[0] - radius).floor() as PosScalar, (center[1] - radius).floor() as PosScalar, (center[2] - vertical).floor() as PosScalar),
+        BlockPos::new((center[0] + radius).floor() as PosScalar, (center[1] + radius).floor() as PosScalar, (center[2] + vertical).floor() as PosScalar));
+    let bounds = match bounds.intersection(&area) {
+        Some(bounds) => bounds,
+        None => { return; }
+    };
+    for z in bounds.min.z.max(1)..=bounds.max.z {
+        for y in bounds.min.y..=bounds.max.y {
+            for x in bounds.min.x..=bounds.max.x {
+                let dx = (x as f64 + 0.5 - center[0]) / radius;
+                let dy = (y as f64 + 0.5 - center[1]) / radius;
+                let dz = (z as f64 + 0.5 - center[2]) / vertical;
+                if dx * dx + dy * dy + dz * dz < 1.0 {
+                    carve(BlockPos::new(x, y, z));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn carved(carvers: &Carvers, area: BlockBox) -> BTreeSet<BlockPos> {
+        let mut carved = BTreeSet::new();
+        carvers.carve(area, |_, _| 100, |pos| { carved.insert(pos); });
+        return carved;
+    }
+
+    fn chunk_area(x: PosScalar, y: PosScalar, chunks: PosScalar) -> BlockBox {
+        let origin = ChunkPos::new(x, y).origin();
+        return BlockBox::new(BlockPos::new(origin.x, origin.y, 0), BlockPos::new(origin.x + chunks * 16 - 1, origin.y + 15, 255));
+    }
+
+    #[test]
+    fn tunnels_cross_chunk_borders() {
+        let options = CarverOptions{ cheese_caves: false, tunnels: true, ravines: true };
+        let carvers = Carvers::new(21, options);
+        let mut crossings = 0;
+        for x in -6..6 {
+            // Carving two chunks at once gives the same blocks as carving them one at a time
+            let both = carved(&carvers, chunk_area(x, 2, 2));
+            let mut separate = carved(&carvers, chunk_area(x, 2, 1));
+            separate.extend(carved(&carvers, chunk_area(x + 1, 2, 1)));
+            assert_eq!(both, separate);
+
+            let border = ChunkPos::new(x + 1, 2).origin().x;
+            crossings += both.iter().filter(|pos| pos.x == border && both.contains(&BlockPos::new(border - 1, pos.y, pos.z))).count();
+        }
+        assert!(crossings > 0);
+    }
+
+    #[test]
+    fn cheese_caves_stay_underground() {
+        let options = CarverOptions{ cheese_caves: true, tunnels: false, ravines: false };
+        let area = chunk_area(0, 0, 4);
+        let carved = carved(&Carvers::new(4, options), area);
+        assert!(carved.iter().all(|pos| pos.z >= MIN_Z && pos.z < 100 - SURFACE_DEPTH));
+        let underground = area.volume() / 256 * (100 - SURFACE_DEPTH - MIN_Z) as usize;
+        assert!(carved.len() > underground / 100 && carved.len() < underground / 5, "{} of {}", carved.len(), underground);
+    }
+
+    #[test]
+    fn carvers_can_be_turned_off() {
+        let mut settings = GeneratorSettings::default();
+        assert_eq!(CarverOptions::from_settings(&settings), Ok(CarverOptions{ cheese_caves: true, tunnels: true, ravines: true }));
+        for option in [CHEESE_CAVES_OPTION, TUNNELS_OPTION, RAVINES_OPTION].iter() {
+            settings.options.insert(option.to_string(), "false".to_string());
+        }
+        let options = CarverOptions::from_settings(&settings).unwrap();
+        assert!(carved(&Carvers::new(21, options), chunk_area(-3, 5, 4)).is_empty());
+
+        settings.options.insert(RAVINES_OPTION.to_string(), "sometimes".to_string());
+        assert!(matches!(CarverOptions::from_settings(&settings), Err(GeneratorError::InvalidOption(_, _))));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::core::*;
+use crate::model::biome::Biome;
+use crate::util::noise::{derive_seed, Fractal, FractalKind, Noise, Simplex};
+
+// Salts of the noise sources, changing one changes the terrain of every seed
+const CONTINENTS_SALT: u64 = 1;
+const MOUNTAINS_SALT: u64 = 4;
+const TEMPERATURE_SALT: u64 = 5;
+const HUMIDITY_SALT: u64 = 6;
+
+// Continentalness below which columns are ocean, and below which land is beach
+const OCEAN: f64 = -0.08;
+const COAST: f64 = 0.02;
+// Mountain mask above which columns are mountains
+const MOUNTAIN_RANGE: f64 = 0.35;
+
+pub fn smoothstep(edge0: f64, edge1: f64, v: f64) -> f64 {
+    let t = ((v - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClimateSample {
+    // All of them roughly in [-1, 1]
+    pub temperature: f64,
+    pub humidity: f64,
+    // Large scale land and ocean, negative out at sea
+    pub continentalness: f64,
+    // In [0, 1], how far into a mountain range the column is, 0 at sea
+    pub mountains: f64,
+}
+
+impl ClimateSample {
+    pub fn biome(&self) -> Biome {
+        if self.continentalness < OCEAN {
+            return Biome::Ocean;
+        }
+        if self.continentalness < COAST {
+            return Biome::Beach;
+        }
+        if self.mountains > MOUNTAIN_RANGE {
+            return Biome::Mountains;
+        }
+        if self.temperature < -0.45 {
+            return Biome::SnowyPlains;
+        }
+        if self.temperature < -0.15 {
+            return Biome::Taiga;
+        }
+        if self.temperature > 0.4 && self.humidity < 0.0 {
+            return Biome::Desert;
+        }
+        if self.temperature > 0.0 && self.humidity > 0.45 {
+            return Biome::Swamp;
+        }
+        if self.humidity > 0.05 {
+            return Biome::Forest;
+        }
+        return Biome::Plains;
+    }
+}
+
+// Noise the biome of every column is picked from, a function of the seed and the column only.
+pub struct Climate {
+    temperature: Fractal<Simplex>,
+    humidity: Fractal<Simplex>,
+    continents: Fractal<Simplex>,
+    // Where mountain ranges rise out of the land
+    mountains: Simplex,
+}
+
+impl Climate {
+    pub fn new(seed: u64) -> Self {
+        let mut temperature = Fractal::new(FractalKind::Fbm, 3, derive_seed(seed, TEMPERATURE_SALT), Simplex::new);
+        temperature.frequency = 1.0 / 900.0;
+        let mut humidity = Fractal::new(FractalKind::Fbm, 3, derive_seed(seed, HUMIDITY_SALT), Simplex::new);
+        humidity.frequency = 1.0 / 700.0;
+        let mut continents = Fractal::new(FractalKind::Fbm, 4, derive_seed(seed, CONTINENTS_SALT), Simplex::new);
+        continents.frequency = 1.0 / 600.0;
+        let mountains = Simplex::new(derive_seed(seed, MOUNTAINS_SALT));
+        return Climate{ temperature, humidity, continents, mountains };
+    }
+
+    pub fn sample(&self, x: PosScalar, y: PosScalar) -> ClimateSample {
+        let (x, y) = (x as f64, y as f64);
+        let continentalness = self.continents.sample2(x, y);
+        let land = smoothstep(-0.05, 0.3, continentalness);
+        return ClimateSample{
+            temperature: self.temperature.sample2(x, y),
+            humidity: self.humidity.sample2(x, y),
+            continentalness,
+            mountains: smoothstep(0.1, 0.6, self.mountains.sample2(x / 1200.0, y / 1200.0)) * land,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_biome_is_reachable() {
+        let climate = Climate::new(3);
+        let mut found: Vec<Biome> = (-100..100).flat_map(|i| (-100..100).map(move |j| (i * 64, j * 64)))
+            .map(|(x, y)| climate.sample(x, y).biome())
+            .collect();
+        found.sort_by_key(|b| b.id());
+        found.dedup();
+        assert_eq!(found, Biome::ALL.to_vec());
+    }
+}
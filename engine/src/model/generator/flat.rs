@@ -0,0 +1,107 @@
+use crate::core::*;
+use crate::model::block::Block;
+use crate::model::chunk::Chunk;
+use crate::model::config;
+use crate::model::level::GeneratorSettings;
+use crate::model::registry::BlockRegistry;
+use super::{GeneratorError, WorldGenerator};
+
+use nalgebra::Vector3;
+use std::sync::Arc;
+
+pub const NAME: &str = "flat";
+
+// Bottom to top, <block>:<thickness> separated by commas
+const LAYERS_OPTION: &str = "layers";
+const DEFAULT_LAYERS: &str = "bedrock:1,stone:3,dirt:2,grass_block:1";
+
+// The same layers of blocks in every column, starting at z = 0.
+pub struct FlatGenerator {
+    settings: GeneratorSettings,
+    registry: Arc<BlockRegistry>,
+    layers: Vec<(Block, PosScalar)>,
+}
+
+impl FlatGenerator {
+    pub fn from_settings(settings: &GeneratorSettings, registry: Arc<BlockRegistry>) -> Result<Self, GeneratorError> {
+        let source = settings.options.get(LAYERS_OPTION).map_or(DEFAULT_LAYERS, |s| s.as_str());
+        let invalid = |reason: String| GeneratorError::InvalidOption(LAYERS_OPTION.to_string(), reason);
+
+        let mut layers = Vec::new();
+        let mut height: PosScalar = 0;
+        for layer in source.split(',').map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let (name, thickness) = match layer.rfind(':') {
+                Some(idx) => (&layer[..idx], &layer[idx + 1..]),
+                None => (layer, "1"),
+            };
+            let block = registry.block(name).ok_or_else(|| invalid(format!("unknown block {}", name)))?;
+            let thickness: PosScalar = thickness.parse().map_err(|_| invalid(format!("invalid thickness {}", thickness)))?;
+            if thickness < 1 {
+                return Err(invalid(format!("invalid thickness {}", thickness)));
+            }
+            height = match height.checked_add(thickness) {
+                Some(height) if height <= (config::BUILD_LIMIT * config::SUBCHUNK_SIZE) as PosScalar => height,
+                _ => { return Err(invalid(format!("{} doesn't fit into the world", layer))); }
+            };
+            layers.push((block, thickness));
+        }
+        return Ok(FlatGenerator{ settings: settings.clone(), registry, layers });
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn settings(&self) -> GeneratorSettings {
+        return self.settings.clone();
+    }
+
+    fn surface_height(&self, _seed: u64, _x: PosScalar, _y: PosScalar) -> PosScalar {
+        return self.layers.iter().map(|(_, thickness)| thickness).sum();
+    }
+
+    fn generate(&self, _seed: u64, chunk: &mut Chunk) {
+        let origin = chunk.pos().origin();
+        let mut z = 0;
+        for (block, thickness) in self.layers.iter() {
+            for _ in 0..*thickness {
+                for y in 0..config::SUBCHUNK_SIZE as PosScalar {
+                    for x in 0..config::SUBCHUNK_SIZE as PosScalar {
+                        chunk.set_block(origin + Vector3::new(x, y, z), *block, &self.registry);
+                    }
+                }
+                z += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_fill_every_column() {
+        let registry = Arc::new(BlockRegistry::builtin());
+        let mut settings = GeneratorSettings{ name: NAME.to_string(), ..Default::default() };
+        settings.options.insert(LAYERS_OPTION.to_string(), "stone:3, dirt:2,grass_block".to_string());
+        let generator = FlatGenerator::from_settings(&settings, registry.clone()).unwrap();
+
+        let chunk = Chunk::generate(ChunkPos::new(-2, 3), 7, &generator);
+        assert!(!chunk.is_dirty());
+        let origin = chunk.pos().origin();
+        for (z, name) in ["stone", "stone", "stone", "dirt", "dirt", "grass_block"].iter().enumerate() {
+            let block = chunk.block(origin + Vector3::new(15, 4, z as PosScalar)).unwrap();
+            assert_eq!(block.block_id(), registry.id(name).unwrap());
+        }
+        assert!(chunk.block(origin + Vector3::new(0, 0, 6)).unwrap().is_air());
+        assert_eq!(chunk.height(crate::model::heightmap::HeightmapKind::WorldSurface, origin.x, origin.y), Some(6));
+
+        settings.options.insert(LAYERS_OPTION.to_string(), "stone:0".to_string());
+        assert!(FlatGenerator::from_settings(&settings, registry.clone()).is_err());
+        settings.options.insert(LAYERS_OPTION.to_string(), "stone:-5,stone:10".to_string());
+        assert!(FlatGenerator::from_settings(&settings, registry.clone()).is_err());
+        settings.options.insert(LAYERS_OPTION.to_string(), "stone:1,stone:2147483647".to_string());
+        assert!(FlatGenerator::from_settings(&settings, registry.clone()).is_err());
+        settings.options.insert(LAYERS_OPTION.to_string(), "stone:x".to_string());
+        assert!(FlatGenerator::from_settings(&settings, registry).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+pub mod carver;
+pub mod climate;
+pub mod flat;
+pub mod overworld;
+
+use crate::core::*;
+use crate::model::chunk::Chunk;
+use crate::model::level::GeneratorSettings;
+use crate::model::registry::BlockRegistry;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratorError {
+    UnknownGenerator(String),
+    // Option name and why its value was rejected
+    InvalidOption(String, String),
+    // A block the generator places isn't in the registry
+    MissingBlock(String),
+}
+
+// Fills freshly created chunks. Runs on the chunk loader threads, so a chunk must only depend on
+// the seed, the settings and its position, never on which chunks were generated before it.
+pub trait WorldGenerator: Send + Sync {
+    // Stored in the level file, from_settings turns them back into the same generator.
+    fn settings(&self) -> GeneratorSettings;
+
+    // Height above the ground of the column, where a player can stand, e.g. to place the spawn point.
+    fn surface_height(&self, seed: u64, x: PosScalar, y: PosScalar) -> PosScalar;
+
+    fn generate(&self, seed: u64, chunk: &mut Chunk);
+}
+
+// Leaves every chunk as air.
+pub struct EmptyGenerator;
+
+impl WorldGenerator for EmptyGenerator {
+    fn settings(&self) -> GeneratorSettings {
+        return GeneratorSettings::default();
+    }
+
+    fn surface_height(&self, _seed: u64, _x: PosScalar, _y: PosScalar) -> PosScalar {
+        return 0;
+    }
+
+    fn generate(&self, _seed: u64, _chunk: &mut Chunk) {}
+}
+
+// Generator of new worlds.
+pub fn default_settings() -> GeneratorSettings {
+    return GeneratorSettings{ name: overworld::NAME.to_string(), ..Default::default() };
+}
+
+// The value of an option, or the default if the settings don't have it.
+pub(crate) fn parse_option<T: FromStr>(settings: &GeneratorSettings, name: &str, default: T) -> Result<T, GeneratorError> {
+    return match settings.options.get(name) {
+        Some(value) => value.trim().parse().map_err(|_| GeneratorError::InvalidOption(name.to_string(), format!("invalid value {}", value))),
+        None => Ok(default),
+    };
+}
+
+pub fn from_settings(settings: &GeneratorSettings, registry: &Arc<BlockRegistry>) -> Result<Arc<dyn WorldGenerator>, GeneratorError> {
+    return match settings.name.as_str() {
+        "empty" => Ok(Arc::new(EmptyGenerator)),
+        flat::NAME => Ok(Arc::new(flat::FlatGenerator::from_settings(settings, registry.clone())?)),
+        overworld::NAME => Ok(Arc::new(overworld::OverworldGenerator::from_settings(settings, registry.clone())?)),
+        name => Err(GeneratorError::UnknownGenerator(name.to_string())),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let registry = Arc::new(BlockRegistry::builtin());
+        let empty = from_settings(&GeneratorSettings::default(), &registry).unwrap();
+        assert_eq!(empty.settings(), GeneratorSettings::default());
+
+        let mut settings = GeneratorSettings{ name: flat::NAME.to_string(), ..Default::default() };
+        settings.options.insert("layers".to_string(), "bedrock:1,stone:3,grass_block:1".to_string());
+        assert_eq!(from_settings(&settings, &registry).unwrap().settings(), settings);
+
+        settings.options.insert("layers".to_string(), "stone:3,cheese:1".to_string());
+        assert!(matches!(from_settings(&settings, &registry), Err(GeneratorError::InvalidOption(_, _))));
+        settings.options.clear();
+        settings.name = overworld::NAME.to_string();
+        settings.options.insert("sea_level".to_string(), "high".to_string());
+        assert!(matches!(from_settings(&settings, &registry), Err(GeneratorError::InvalidOption(_, _))));
+        let only_stone = Arc::new(BlockRegistry::from_ron("[(name: \"air\"), (name: \"stone\")]").unwrap());
+        assert_eq!(from_settings(&default_settings(), &only_stone).err(), Some(GeneratorError::MissingBlock("bedrock".to_string())));
+        settings.name = "caves".to_string();
+        assert_eq!(from_settings(&settings, &registry).err(), Some(GeneratorError::UnknownGenerator("caves".to_string())));
+    }
+}
@@ -0,0 +1,537 @@
+use crate::core::*;
+use crate::model::biome::{Biome, BiomeMap, Vegetation};
+use crate::model::block::Block;
+use crate::model::chunk::{Chunk, SubChunk};
+use crate::model::config;
+use crate::model::heightmap::HeightmapKind;
+use crate::model::level::GeneratorSettings;
+use crate::model::palette::PalettedStorage;
+use crate::model::registry::BlockRegistry;
+use crate::util::noise::{derive_seed, Fractal, FractalKind, Noise, Perlin, Simplex};
+use super::carver::{CarverOptions, Carvers};
+use super::climate::Climate;
+use super::{parse_option, GeneratorError, WorldGenerator};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const NAME: &str = "overworld";
+
+const SEA_LEVEL_OPTION: &str = "sea_level";
+const DEFAULT_SEA_LEVEL: PosScalar = 62;
+const WORLD_HEIGHT: PosScalar = (config::BUILD_LIMIT * config::SUBCHUNK_SIZE) as PosScalar;
+const SIZE: PosScalar = config::SUBCHUNK_SIZE as PosScalar;
+
+// Layers below the surface, in blocks
+const DIRT_DEPTH: PosScalar = 4;
+// Mountain tops above this height are bare stone
+const ROCK_LINE: PosScalar = 150;
+
+// Terrain parameters are averaged over the biomes of a grid of columns, BLEND_STEP blocks apart,
+// within BLEND_RADIUS blocks of the column
+const BLEND_STEP: PosScalar = 4;
+const BLEND_RADIUS: PosScalar = 8;
+
+// Leaves reach this far from the trunk, trees in neighbouring chunks grow into this one
+const TREE_RADIUS: PosScalar = 2;
+const MAX_TRUNK: PosScalar = 6;
+// Columns around the chunk that are generated along with it, far enough to know which trees grow
+// into the chunk and whether the carvers left the ground under them
+const MARGIN: PosScalar = TREE_RADIUS + 1;
+
+// Salts of the noise sources, changing one changes the terrain of every seed
+const HILLS_SALT: u64 = 2;
+const RIDGES_SALT: u64 = 3;
+const VEGETATION_SALT: u64 = 7;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Column {
+    // Number of solid blocks in the column, the top one is at height - 1
+    height: PosScalar,
+    biome: Biome,
+}
+
+// Height and biome of every column, a function of the seed and the column only.
+struct Terrain {
+    climate: Climate,
+    hills: Fractal<Perlin>,
+    ridges: Fractal<Simplex>,
+    sea_level: PosScalar,
+    // Biomes of the blending grid, shared by the columns of a chunk
+    grid: HashMap<(PosScalar, PosScalar), Biome>,
+}
+
+impl Terrain {
+    fn new(seed: u64, sea_level: PosScalar) -> Self {
+        let mut hills = Fractal::new(FractalKind::Fbm, 4, derive_seed(seed, HILLS_SALT), Perlin::new);
+        hills.frequency = 1.0 / 120.0;
+        let mut ridges = Fractal::new(FractalKind::Ridged, 5, derive_seed(seed, RIDGES_SALT), Simplex::new);
+        ridges.frequency = 1.0 / 300.0;
+        return Terrain{ climate: Climate::new(seed), hills, ridges, sea_level, grid: HashMap::new() };
+    }
+
+    // Weighted average of the terrain parameters of the biomes around the column, so heights don't
+    // jump at biome borders: (height offset, hill scale, mountain scale).
+    fn blend(&mut self, x: PosScalar, y: PosScalar) -> (f64, f64, f64) {
+        let (mut offset, mut hills, mut mountains, mut total) = (0.0, 0.0, 0.0, 0.0);
+        let min = |v: PosScalar| (v - BLEND_RADIUS).div_euclid(BLEND_STEP) * BLEND_STEP;
+        let radius = (BLEND_RADIUS * BLEND_RADIUS) as f64;
+        for gy in (min(y)..=y + BLEND_RADIUS).step_by(BLEND_STEP as usize) {
+            for gx in (min(x)..=x + BLEND_RADIUS).step_by(BLEND_STEP as usize) {
+                let distance = ((gx - x) * (gx - x) + (gy - y) * (gy - y)) as f64;
+                if distance >= radius {
+                    continue;
+                }
+                let falloff = 1.0 - distance / radius;
+                let weight = falloff * falloff;
+                let climate = &self.climate;
+                let properties = self.grid.entry((gx, gy)).or_insert_with(|| climate.sample(gx, gy).biome()).properties();
+                offset += properties.height_offset * weight;
+                hills += properties.hill_scale * weight;
+                mountains += properties.mountain_scale * weight;
+                total += weight;
+            }
+        }
+        return (offset / total, hills / total, mountains / total);
+    }
+
+    fn column(&mut self, x: PosScalar, y: PosScalar) -> Column {
+        let climate = self.climate.sample(x, y);
+        let (offset, hill_scale, mountain_scale) = self.blend(x, y);
+        let (fx, fy) = (x as f64, y as f64);
+        let ridges = (self.ridges.sample2(fx, fy) + 1.0) * 0.5;
+        let height = self.sea_level as f64 + climate.continentalness * 28.0 + offset
+            + self.hills.sample2(fx, fy) * hill_scale
+            + climate.mountains * ridges * mountain_scale;
+        return Column{ height: (height.floor() as PosScalar).clamp(1, WORLD_HEIGHT - 1), biome: climate.biome() };
+    }
+}
+
+// Uniform in [0, 1), the same for a column whenever it is asked for.
+fn column_random(seed: u64, x: PosScalar, y: PosScalar) -> f64 {
+    let column = ((x as u32 as u64) << 32) | y as u32 as u64;
+    return (derive_seed(derive_seed(seed, VEGETATION_SALT), column) >> 11) as f64 / (1u64 << 53) as f64;
+}
+
+// Blocks of a chunk and the margin around it while it's generated, z up to a multiple of the
+// sub-chunk size.
+struct ChunkBlocks {
+    min: BlockPos,
+    width: PosScalar,
+    height: PosScalar,
+    blocks: Vec<Block>,
+}
+
+impl ChunkBlocks {
+    fn index(&self, x: PosScalar, y: PosScalar, z: PosScalar) -> Option<usize> {
+        let (x, y) = (x - self.min.x, y - self.min.y);
+        if x < 0 || y < 0 || x >= self.width || y >= self.width || z < 0 || z >= self.height {
+            return None;
+        }
+        return Some(((z * self.width + y) * self.width + x) as usize);
+    }
+
+    fn get(&self, x: PosScalar, y: PosScalar, z: PosScalar) -> Option<Block> {
+        return self.index(x, y, z).map(|index| self.blocks[index]);
+    }
+
+    fn set(&mut self, x: PosScalar, y: PosScalar, z: PosScalar, block: Block) {
+        if let Some(index) = self.index(x, y, z) {
+            self.blocks[index] = block;
+        }
+    }
+
+    fn set_if_air(&mut self, x: PosScalar, y: PosScalar, z: PosScalar, block: Block) {
+        if let Some(index) = self.index(x, y, z) {
+            if self.blocks[index].is_air() {
+                self.blocks[index] = block;
+            }
+        }
+    }
+}
+
+// Hills, mountain ranges and oceans filled with water up to the sea level. Every column gets the
+// surface and vegetation of its biome, the ground under the water is sand.
+pub struct OverworldGenerator {
+    settings: GeneratorSettings,
+    registry: Arc<BlockRegistry>,
+    sea_level: PosScalar,
+    bedrock: Block,
+    stone: Block,
+    sand: Block,
+    water: Block,
+    log: Block,
+    leaves: Block,
+    tall_grass: Block,
+    cactus: Block,
+    // Surface and filler blocks, indexed by biome id
+    surfaces: Vec<(Block, Block)>,
+    carvers: CarverOptions,
+}
+
+impl OverworldGenerator {
+    pub fn from_settings(settings: &GeneratorSettings, registry: Arc<BlockRegistry>) -> Result<Self, GeneratorError> {
+        let sea_level = parse_option(settings, SEA_LEVEL_OPTION, DEFAULT_SEA_LEVEL)?;
+        if !(1..WORLD_HEIGHT).contains(&sea_level) {
+            return Err(GeneratorError::InvalidOption(SEA_LEVEL_OPTION.to_string(), format!("{} is outside of the world", sea_level)));
+        }
+        let block = |name: &str| registry.block(name).ok_or_else(|| GeneratorError::MissingBlock(name.to_string()));
+        let (bedrock, stone, sand, water) = (block("bedrock")?, block("stone")?, block("sand")?, block("water")?);
+        let mut surfaces = Vec::with_capacity(Biome::ALL.len());
+        for biome in Biome::ALL.iter() {
+            surfaces.push((block(biome.properties().surface)?, block(biome.properties().filler)?));
+        }
+        return Ok(OverworldGenerator{
+            settings: settings.clone(),
+            sea_level,
+            bedrock,
+            stone,
+            sand,
+            water,
+            log: block("log")?,
+            leaves: block("leaves")?,
+            tall_grass: block("tall_grass")?,
+            cactus: block("cactus")?,
+            surfaces,
+            carvers: CarverOptions::from_settings(settings)?,
+            registry,
+        });
+    }
+
+    fn block_at(&self, column: Column, z: PosScalar) -> Block {
+        if z == 0 {
+            return self.bedrock;
+        }
+        if z >= column.height {
+            return if z < self.sea_level { self.water } else { Block::AIR };
+        }
+        if z < column.height - DIRT_DEPTH || column.height > ROCK_LINE {
+            return self.stone;
+        }
+        if column.height <= self.sea_level {
+            return self.sand;
+        }
+        let (surface, filler) = self.surfaces[column.biome.id() as usize];
+        return if z == column.height - 1 { surface } else { filler };
+    }
+
+    // What grows on the column, None where nothing does. Plants only grow on the surface of their
+    // biome above the water.
+    fn plant(&self, seed: u64, x: PosScalar, y: PosScalar, column: Column) -> Option<(Vegetation, PosScalar)> {
+        let properties = column.biome.properties();
+        if column.height <= self.sea_level || column.height > ROCK_LINE || column.height + MAX_TRUNK + 2 >= WORLD_HEIGHT {
+            return None;
+        }
+        let random = column_random(seed, x, y);
+        if random >= properties.vegetation_density {
+            return None;
+        }
+        // The same random value picks the size, it's uniform in [0, density)
+        let size = (random / properties.vegetation_density * 3.0) as PosScalar;
+        return match properties.vegetation {
+            Vegetation::None => None,
+            Vegetation::TallGrass => Some((Vegetation::TallGrass, 1)),
+            Vegetation::Cactus => Some((Vegetation::Cactus, 1 + size)),
+            Vegetation::Trees => Some((Vegetation::Trees, MAX_TRUNK - 2 + size)),
+        };
+    }
+
+    // Carvers never open up the sea floor or the shore, water stays where it was generated.
+    fn can_carve(&self, blocks: &ChunkBlocks, pos: BlockPos) -> bool {
+        let block = blocks.get(pos.x, pos.y, pos.z);
+        if block.is_none_or(|block| block.is_air() || block == self.water || block == self.bedrock) {
+            return false;
+        }
+        let neighbours = [(0, 0, 1), (1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0)];
+        return neighbours.iter().all(|(dx, dy, dz)| blocks.get(pos.x + dx, pos.y + dy, pos.z + dz) != Some(self.water));
+    }
+
+    fn grow_leaves(&self, blocks: &mut ChunkBlocks, x: PosScalar, y: PosScalar, base: PosScalar, trunk: PosScalar) {
+        let top = base + trunk;
+        for z in top - 2..=top {
+            let radius = if z >= top - 1 { 1 } else { TREE_RADIUS };
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    // Rounded corners
+                    if radius == TREE_RADIUS && dx.abs() == radius && dy.abs() == radius {
+                        continue;
+                    }
+                    blocks.set_if_air(x + dx, y + dy, z, self.leaves);
+                }
+            }
+        }
+    }
+}
+
+impl WorldGenerator for OverworldGenerator {
+    fn settings(&self) -> GeneratorSettings {
+        return self.settings.clone();
+    }
+
+    // Carvers and plants change the ground, so the height is only known once the whole chunk is
+    // generated.
+    fn surface_height(&self, seed: u64, x: PosScalar, y: PosScalar) -> PosScalar {
+        let chunk = Chunk::generate(BlockPos::new(x, y, 0).chunk(), seed, self);
+        return chunk.height(HeightmapKind::MotionBlocking, x, y).unwrap();
+    }
+
+    // Base terrain first, then the carvers hollow it out and plants grow on what's left of the
+    // ground. Leaves of trees never replace anything, so trees growing into each other look the
+    // same whichever chunk is generated first. Whole sub-chunks are filled at once, visibility and
+    // heightmaps are computed once at the end.
+    fn generate(&self, seed: u64, chunk: &mut Chunk) {
+        let mut terrain = Terrain::new(seed, self.sea_level);
+        let origin = chunk.pos().origin();
+        let min = BlockPos::new(origin.x - MARGIN, origin.y - MARGIN, 0);
+        let width = SIZE + 2 * MARGIN;
+        let mut columns = HashMap::new();
+        for y in min.y..min.y + width {
+            for x in min.x..min.x + width {
+                columns.insert((x, y), terrain.column(x, y));
+            }
+        }
+
+        let top = columns.values().map(|c| c.height + MAX_TRUNK + 2).max().unwrap().max(self.sea_level);
+        let height = ((top + SIZE - 1) / SIZE * SIZE).min(WORLD_HEIGHT);
+        let mut blocks = ChunkBlocks{ min, width, height, blocks: vec![Block::AIR; (width * width * height) as usize] };
+        for ((x, y), column) in columns.iter() {
+            for z in 0..column.height.max(self.sea_level) {
+                blocks.set(*x, *y, z, self.block_at(*column, z));
+            }
+        }
+
+        let mut carved = Vec::new();
+        let area = BlockBox::new(min, BlockPos::new(min.x + width - 1, min.y + width - 1, height - 1));
+        Carvers::new(seed, self.carvers).carve(area, |x, y| columns[&(x, y)].height, |pos| carved.push(pos));
+        carved.retain(|pos| self.can_carve(&blocks, *pos));
+        for pos in carved {
+            blocks.set(pos.x, pos.y, pos.z, Block::AIR);
+        }
+
+        // Trees further away than this don't grow into the chunk
+        let reaches = |v: PosScalar, origin: PosScalar| (origin - TREE_RADIUS..origin + SIZE + TREE_RADIUS).contains(&v);
+        let mut plants: Vec<(PosScalar, PosScalar, PosScalar, Vegetation, PosScalar)> = columns.iter()
+            .filter(|((x, y), _)| reaches(*x, origin.x) && reaches(*y, origin.y))
+            .filter(|((x, y), column)| blocks.get(*x, *y, column.height - 1).is_some_and(|block| !block.is_air()))
+            .filter_map(|((x, y), column)| self.plant(seed, *x, *y, *column).map(|(kind, size)| (*x, *y, column.height, kind, size)))
+            .collect();
+        plants.sort_by_key(|(x, y, _, _, _)| (*y, *x));
+        for (x, y, base, kind, size) in plants.iter() {
+            if *kind == Vegetation::Trees {
+                self.grow_leaves(&mut blocks, *x, *y, *base, *size);
+            }
+        }
+        for (x, y, base, kind, size) in plants {
+            let block = match kind {
+                Vegetation::TallGrass => self.tall_grass,
+                Vegetation::Cactus => self.cactus,
+                _ => self.log,
+            };
+            for z in base..base + size {
+                blocks.set(x, y, z, block);
+            }
+        }
+
+        let mut biomes = BiomeMap::new(Biome::DEFAULT);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                biomes.set_biome(x as u8, y as u8, columns[&(origin.x + x, origin.y + y)].biome);
+            }
+        }
+        for index in 0..height / SIZE {
+            let mut storage = PalettedStorage::new(LocalPos::VOLUME, Block::AIR);
+            for z in 0..SIZE {
+                for y in 0..SIZE {
+                    for x in 0..SIZE {
+                        let block = blocks.get(origin.x + x, origin.y + y, index * SIZE + z).unwrap();
+                        storage.set(LocalPos::new(x as u8, y as u8, z as u8).index(), block);
+                    }
+                }
+            }
+            let subchunk = SubChunk::from_storage(storage).unwrap();
+            if !subchunk.is_empty() {
+                chunk.set_subchunk(chunk.pos().subchunk(index), subchunk);
+            }
+        }
+        chunk.set_biomes(biomes);
+        chunk.refresh_all_visibility(&self.registry);
+        chunk.recompute_heightmaps(&self.registry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block::Face;
+    use nalgebra::Vector3;
+
+    fn generator_with(options: &[(&str, &str)]) -> OverworldGenerator {
+        let mut settings = GeneratorSettings{ name: NAME.to_string(), ..Default::default() };
+        for (name, value) in options.iter() {
+            settings.options.insert(name.to_string(), value.to_string());
+        }
+        return OverworldGenerator::from_settings(&settings, Arc::new(BlockRegistry::builtin())).unwrap();
+    }
+
+    fn generator() -> OverworldGenerator {
+        return generator_with(&[]);
+    }
+
+    fn without_carvers() -> OverworldGenerator {
+        return generator_with(&[("cheese_caves", "false"), ("tunnels", "false"), ("ravines", "false")]);
+    }
+
+    #[test]
+    fn chunks_do_not_depend_on_generation_order() {
+        let positions = [ChunkPos::new(0, 0), ChunkPos::new(1, 0), ChunkPos::new(0, 1), ChunkPos::new(-7, 3)];
+        let forward: Vec<Chunk> = positions.iter().map(|pos| Chunk::generate(*pos, 17, &generator())).collect();
+        let generator = generator();
+        for (pos, chunk) in positions.iter().zip(forward.iter()).rev() {
+            let again = Chunk::generate(*pos, 17, &generator);
+            assert!(again.blocks().eq(chunk.blocks()));
+            assert_eq!(again.heightmaps(), chunk.heightmaps());
+            assert_eq!(again.biomes(), chunk.biomes());
+        }
+
+        // Neighbouring columns on both sides of a seam
+        let (left, right) = (&forward[0], &forward[1]);
+        for y in 0..16 {
+            let a = left.height(HeightmapKind::Solid, 15, y).unwrap();
+            let b = right.height(HeightmapKind::Solid, 16, y).unwrap();
+            assert!((a - b).abs() <= 3, "{} and {} at the seam", a, b);
+        }
+        assert!(!Chunk::generate(ChunkPos::new(0, 0), 18, &generator).blocks().eq(forward[0].blocks()));
+    }
+
+    #[test]
+    fn columns_are_layered() {
+        let generator = without_carvers();
+        let registry = BlockRegistry::builtin();
+        let mut terrain = Terrain::new(5, DEFAULT_SEA_LEVEL);
+        let heights: Vec<PosScalar> = (-80..80).flat_map(|i| (-80..80).map(move |j| (i * 64, j * 64)))
+            .map(|(x, y)| terrain.column(x, y).height).collect();
+        assert!(heights.iter().any(|h| *h < DEFAULT_SEA_LEVEL - 10));
+        assert!(heights.iter().any(|h| *h > DEFAULT_SEA_LEVEL + 60));
+        assert!(heights.iter().all(|h| *h >= 1 && *h < WORLD_HEIGHT));
+
+        let name = |block: &Block| registry.definition(block.block_id()).unwrap().name.clone();
+        for pos in [ChunkPos::new(0, 0), ChunkPos::new(12, -30), ChunkPos::new(-40, 9)].iter() {
+            let chunk = Chunk::generate(*pos, 5, &generator);
+            let origin = pos.origin();
+            for (x, y) in [(0, 0), (7, 11), (15, 15)].iter() {
+                let column = terrain.column(origin.x + x, origin.y + y);
+                let height = column.height;
+                let block = |z: PosScalar| name(chunk.block(origin + Vector3::new(*x, *y, z)).unwrap());
+                assert_eq!(block(0), "bedrock");
+                assert_eq!(block(height - DIRT_DEPTH - 1), "stone");
+                let properties = column.biome.properties();
+                let (surface, filler) = if height <= DEFAULT_SEA_LEVEL { ("sand", "sand") }
+                    else if height > ROCK_LINE { ("stone", "stone") }
+                    else { (properties.surface, properties.filler) };
+                assert_eq!(block(height - 1), surface);
+                assert_eq!(block(height - 2), filler);
+                if height < DEFAULT_SEA_LEVEL {
+                    assert_eq!(block(DEFAULT_SEA_LEVEL - 1), "water");
+                    assert_eq!(block(DEFAULT_SEA_LEVEL), "air");
+                }
+                assert_eq!(chunk.biome(origin.x + x, origin.y + y), Some(column.biome));
+            }
+        }
+    }
+
+    #[test]
+    fn biomes_shape_the_terrain() {
+        let generator = generator();
+        let climate = Climate::new(11);
+        let mut terrain = Terrain::new(11, DEFAULT_SEA_LEVEL);
+
+        // Heights change smoothly where biomes meet
+        let mut borders = 0;
+        for i in -300..300 {
+            let (x, y) = (i * 16, i * -8);
+            let (a, b) = (terrain.column(x, y), terrain.column(x + 1, y));
+            if a.biome != b.biome {
+                borders += 1;
+                assert!((a.height - b.height).abs() <= 4, "{:?} next to {:?}", a, b);
+            }
+        }
+        assert!(borders > 0);
+
+        // Forests grow trees, deserts cacti
+        for (biome, plant) in [(Biome::Forest, "log"), (Biome::Desert, "cactus")].iter() {
+            let column = (-200..200).flat_map(|i| (-200..200).map(move |j| (i * 48, j * 48)))
+                .find(|(x, y)| (-24..=24).step_by(8).all(|d| climate.sample(x + d, y + d).biome() == *biome && climate.sample(x + d, y - d).biome() == *biome))
+                .unwrap();
+            let plant = generator.registry.id(plant).unwrap();
+            let mut found = false;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let chunk = Chunk::generate(BlockPos::new(column.0 + dx * 16, column.1 + dy * 16, 0).chunk(), 11, &generator);
+                    found |= chunk.blocks().any(|(_, block)| block.block_id() == plant);
+                }
+            }
+            assert!(found, "no {:?} in {:?}", plant, biome);
+        }
+    }
+
+    #[test]
+    fn surface_is_above_caves_and_plants() {
+        let generator = generator();
+        let mut terrain = Terrain::new(3, DEFAULT_SEA_LEVEL);
+        let mut changed = 0;
+        for pos in [ChunkPos::new(0, 0), ChunkPos::new(5, -2), ChunkPos::new(-9, 14)].iter() {
+            let chunk = Chunk::generate(*pos, 3, &generator);
+            let origin = pos.origin();
+            for i in 0..SIZE {
+                let (x, y) = (origin.x + i, origin.y + (i * 7) % SIZE);
+                let height = generator.surface_height(3, x, y);
+                let solid = |z: PosScalar| generator.registry.properties(chunk.block(BlockPos::new(x, y, z)).unwrap()).solid;
+                assert!(!chunk.block(BlockPos::new(x, y, height - 1)).unwrap().is_air());
+                assert!(!(height..WORLD_HEIGHT).any(solid));
+                if height != terrain.column(x, y).height.max(DEFAULT_SEA_LEVEL) {
+                    changed += 1;
+                }
+            }
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn carvers_do_not_breach_oceans() {
+        let generator = generator();
+        let climate = Climate::new(8);
+        let registry = &generator.registry;
+        let water = registry.id("water").unwrap();
+        let column = (-200..200).flat_map(|i| (-200..200).map(move |j| (i * 48, j * 48)))
+            .find(|(x, y)| climate.sample(*x, *y).biome() == Biome::Beach && climate.sample(x + 40, *y).biome() == Biome::Ocean)
+            .unwrap();
+        let mut chunks = HashMap::new();
+        let mut carved = 0;
+        for dy in -2..=2 {
+            for dx in -2..=5 {
+                let pos = BlockPos::new(column.0 + dx * 16, column.1 + dy * 16, 0).chunk();
+                let chunk = Chunk::generate(pos, 8, &generator);
+                let flat = Chunk::generate(pos, 8, &without_carvers());
+                carved += chunk.blocks().filter(|(pos, block)| block.is_air() && !flat.block(*pos).unwrap().is_air()).count();
+                chunks.insert(pos, chunk);
+            }
+        }
+        let mut water_blocks = 0;
+        for chunk in chunks.values() {
+            for (pos, block) in chunk.blocks() {
+                if block.block_id() != water {
+                    continue;
+                }
+                water_blocks += 1;
+                for face in [Face::Down, Face::East, Face::West, Face::North, Face::South].iter() {
+                    let neighbour = pos + face.offset();
+                    // Neighbours in chunks around the generated area aren't known
+                    if let Some(other) = chunks.get(&neighbour.chunk()) {
+                        assert!(!other.block(neighbour).unwrap().is_air(), "water at {:?} flows into a cave", pos);
+                    }
+                }
+            }
+        }
+        assert!(carved > 0 && water_blocks > 0);
+    }
+}
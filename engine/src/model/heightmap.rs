@@ -0,0 +1,85 @@
+use crate::core::*;
+use crate::model::config;
+use crate::model::block::Block;
+use crate::model::registry::BlockRegistry;
+
+use serde::{Deserialize, Serialize};
+
+pub const COLUMNS: usize = (config::SUBCHUNK_SIZE * config::SUBCHUNK_SIZE) as usize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HeightmapKind {
+    // Any block other than air
+    WorldSurface,
+    Solid,
+    Opaque,
+    // Solid blocks and fluids, used for rain and spawning
+    MotionBlocking,
+}
+
+impl HeightmapKind {
+    pub const ALL: [HeightmapKind; 4] = [
+        HeightmapKind::WorldSurface,
+        HeightmapKind::Solid,
+        HeightmapKind::Opaque,
+        HeightmapKind::MotionBlocking,
+    ];
+
+    pub fn index(&self) -> usize {
+        return *self as usize;
+    }
+
+    pub fn matches(&self, block: &Block, registry: &BlockRegistry) -> bool {
+        if block.is_air() {
+            return false;
+        }
+        let definition = registry.properties(block);
+        return match self {
+            HeightmapKind::WorldSurface => true,
+            HeightmapKind::Solid => definition.solid,
+            HeightmapKind::Opaque => definition.opaque,
+            HeightmapKind::MotionBlocking => definition.solid || definition.fluid,
+        };
+    }
+}
+
+// Per column height of a chunk: the z right above the highest matching block, 0 for columns without one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Heightmap {
+    kind: HeightmapKind,
+    heights: Vec<u16>,
+}
+
+impl Heightmap {
+    pub fn new(kind: HeightmapKind) -> Self {
+        return Heightmap{ kind, heights: vec![0; COLUMNS] };
+    }
+
+    // Returns None if the number of columns is wrong.
+    pub fn from_heights(kind: HeightmapKind, heights: Vec<u16>) -> Option<Self> {
+        if heights.len() != COLUMNS {
+            return None;
+        }
+        return Some(Heightmap{ kind, heights });
+    }
+
+    pub fn kind(&self) -> HeightmapKind {
+        return self.kind;
+    }
+
+    pub fn heights(&self) -> &[u16] {
+        return &self.heights;
+    }
+
+    fn column(x: u8, y: u8) -> usize {
+        return y as usize * config::SUBCHUNK_SIZE as usize + x as usize;
+    }
+
+    pub fn height(&self, x: u8, y: u8) -> PosScalar {
+        return self.heights[Heightmap::column(x, y)] as PosScalar;
+    }
+
+    pub fn set_height(&mut self, x: u8, y: u8, height: PosScalar) {
+        self.heights[Heightmap::column(x, y)] = height as u16;
+    }
+}
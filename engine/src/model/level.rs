@@ -0,0 +1,48 @@
+use crate::core::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Version of the world directory layout and the level file, bumped on incompatible changes.
+// 1: initial layout
+// 2: region files with chunk checksums and previous copies
+// 3: last_played
+// Older versions are upgraded by io::fs::migration.
+pub const LEVEL_FORMAT_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneratorSettings {
+    pub name: String,
+    pub options: BTreeMap<String, String>,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        return GeneratorSettings{ name: "empty".to_string(), options: BTreeMap::new() };
+    }
+}
+
+// Contents of level.ron in the world directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LevelData {
+    pub format_version: u32,
+    pub seed: u64,
+    pub spawn: BlockPos,
+    pub time: u64, // In milliseconds of game time
+    pub generator: GeneratorSettings,
+    pub last_played: u64, // In seconds since the unix epoch
+}
+
+impl LevelData {
+    pub fn new(seed: u64) -> Self {
+        return LevelData{
+            format_version: LEVEL_FORMAT_VERSION,
+            seed,
+            spawn: BlockPos::new(0, 0, 0),
+            time: 0,
+            generator: GeneratorSettings::default(),
+            last_played: 0,
+        };
+    }
+}
@@ -0,0 +1,53 @@
+use crate::core::*;
+
+pub const MAX_LIGHT: u8 = 15;
+pub const LIGHT_ARRAY_BYTES: usize = LocalPos::VOLUME / 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightKind {
+    Sky,
+    Block,
+}
+
+// One 4 bit light level per block of a sub-chunk, two levels per byte.
+#[derive(Clone, PartialEq, Eq)]
+pub struct LightArray {
+    data: Box<[u8]>,
+}
+
+impl std::fmt::Debug for LightArray {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "LightArray{{..}}");
+    }
+}
+
+impl LightArray {
+    pub fn new(level: u8) -> Self {
+        assert!(level <= MAX_LIGHT);
+        return LightArray{ data: vec![level | level << 4; LIGHT_ARRAY_BYTES].into_boxed_slice() };
+    }
+
+    // Returns None if the slice has the wrong length.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != LIGHT_ARRAY_BYTES {
+            return None;
+        }
+        return Some(LightArray{ data: bytes.into() });
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.data[..];
+    }
+
+    pub fn get(&self, pos: LocalPos) -> u8 {
+        let idx = pos.index();
+        return (self.data[idx / 2] >> ((idx % 2) * 4)) & MAX_LIGHT;
+    }
+
+    pub fn set(&mut self, pos: LocalPos, level: u8) {
+        assert!(level <= MAX_LIGHT);
+        let idx = pos.index();
+        let shift = (idx % 2) * 4;
+        self.data[idx / 2] = (self.data[idx / 2] & !(MAX_LIGHT << shift)) | (level << shift);
+    }
+}
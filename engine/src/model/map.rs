@@ -1,44 +1,788 @@
 use crate::core::*;
 
-use crate::model::config;
+use crate::model::block;
 use crate::model::chunk;
-use nalgebra::Vector2;
- 
-const MAP_AREA: usize = config::MAP_SIZE as usize * config::MAP_SIZE as usize;
+use crate::model::config;
+use crate::model::generator::{EmptyGenerator, WorldGenerator};
+use crate::model::registry::BlockRegistry;
+use crate::model::ticket::{LoadLevel, Ticket, TicketId, TicketSet};
+use crate::model::world::WorldError;
+use crate::task::task_queue::TaskQueue;
+
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+// Where chunks come from once they are needed and where they go once they aren't.
+pub trait ChunkSource: Send + Sync {
+    // Runs on worker threads. None for chunks that were never stored, they get generated.
+    fn load_chunk(&self, pos: ChunkPos) -> Result<Option<chunk::Chunk>, WorldError>;
+
+    // Returns false if there is nowhere to store the chunk, it stays resident then.
+    fn save_chunk(&self, chunk: &chunk::Chunk) -> Result<bool, WorldError>;
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ChunkCacheStats {
+    // Chunks a ticket started to need that were still resident
+    pub hits: u64,
+    // Chunks a ticket started to need that had to be loaded or generated
+    pub misses: u64,
+    pub evictions: u64,
+    pub resident_bytes: usize,
+}
 
 #[derive(Clone)]
+struct ResidentChunk {
+    chunk: chunk::Chunk,
+    // Approximate, refreshed by update_chunks
+    bytes: usize,
+    // Value of Map::clock when a ticket last needed the chunk or it was last modified
+    last_used: u64,
+}
+
+// Chunks some ticket needs are resident, anything else reads as not loaded. Without a memory budget
+// chunks are unloaded as soon as no ticket needs them, with one they stay cached until the budget is
+// exceeded, and the least recently used ones go first.
+// Chunks are loaded and generated in the background, each update_chunks picks up what finished since
+// the previous one.
 pub struct Map {
-    loaded_chunks: Box<[chunk::Chunk]>
+    loaded_chunks: HashMap<ChunkPos, ResidentChunk>,
+    loads: TaskQueue<ChunkPos, Result<chunk::Chunk, WorldError>>,
+    // Chunks that failed to load, they aren't requested again until the load levels change
+    failed_loads: HashSet<ChunkPos>,
+    // Errors of failed loads update_chunks didn't report yet, one is reported per update
+    load_errors: VecDeque<WorldError>,
+    tickets: TicketSet,
+    // Computed from the tickets by the last update_chunks
+    levels: HashMap<ChunkPos, LoadLevel>,
+    memory_budget: Option<usize>,
+    stats: ChunkCacheStats,
+    // Incremented by every update_chunks
+    clock: u64,
+    // Fills chunks the source never stored
+    generator: Arc<dyn WorldGenerator>,
+    seed: u64,
+    // Decides which faces of chunks coming in are hidden by their neighbours
+    registry: Arc<BlockRegistry>,
 }
 
 impl Map {
     pub fn new() -> Box<Self> {
-        let mut chunks = Vec::<chunk::Chunk>::new();
-        chunks.reserve_exact(MAP_AREA);
-        
-        let signed_size = config::MAP_SIZE as PosScalar;
-        
-        for y in -signed_size..signed_size {
-            for x in -signed_size..signed_size {
-                let pos = Vector2::<PosScalar>::new(x,y);
-                chunks.push(chunk::Chunk::generate(pos));
+        return Map::with_registry(Arc::new(BlockRegistry::builtin()));
+    }
+
+    pub fn with_registry(registry: Arc<BlockRegistry>) -> Box<Self> {
+        return Box::new(Map{
+            loaded_chunks: HashMap::new(),
+            loads: TaskQueue::new("chunk-loader", config::CHUNK_WORKER_THREADS),
+            failed_loads: HashSet::new(),
+            load_errors: VecDeque::new(),
+            tickets: TicketSet::new(),
+            levels: HashMap::new(),
+            memory_budget: None,
+            stats: ChunkCacheStats::default(),
+            clock: 0,
+            generator: Arc::new(EmptyGenerator),
+            seed: 0,
+            registry,
+        });
+    }
+
+    pub fn loaded_chunk(&self, pos: ChunkPos) -> Option<&chunk::Chunk> {
+        return self.loaded_chunks.get(&pos).map(|r| &r.chunk);
+    }
+
+    pub fn loaded_chunk_mut(&mut self, pos: ChunkPos) -> Option<&mut chunk::Chunk> {
+        let clock = self.clock;
+        return self.loaded_chunks.get_mut(&pos).map(|r| { r.last_used = clock; &mut r.chunk });
+    }
+
+    pub fn is_loaded(&self, pos: ChunkPos) -> bool {
+        return self.loaded_chunks.contains_key(&pos);
+    }
+
+    pub fn loaded_count(&self) -> usize {
+        return self.loaded_chunks.len();
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &chunk::Chunk> {
+        return self.loaded_chunks.values().map(|r| &r.chunk);
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut chunk::Chunk> {
+        return self.loaded_chunks.values_mut().map(|r| &mut r.chunk);
+    }
+
+    // Makes the chunk resident, returns the one it replaced. It's unloaded by the next
+    // update_chunks if no ticket needs it. Faces on the borders with loaded neighbours are
+    // refreshed on both sides, which doesn't make either chunk dirty.
+    pub fn insert_chunk(&mut self, chunk: chunk::Chunk) -> Option<chunk::Chunk> {
+        let pos = chunk.pos();
+        self.loads.cancel(&pos);
+        let bytes = chunk.memory_usage();
+        self.stats.resident_bytes += bytes;
+        let previous = self.loaded_chunks.insert(pos, ResidentChunk{ chunk, bytes, last_used: self.clock });
+        let previous = previous.map(|r| { self.stats.resident_bytes -= r.bytes; r.chunk });
+        self.refresh_borders(pos);
+        return previous;
+    }
+
+    fn refresh_borders(&mut self, pos: ChunkPos) {
+        let registry = self.registry.clone();
+        let size = config::SUBCHUNK_SIZE as PosScalar;
+        for face in [block::Face::East, block::Face::West, block::Face::North, block::Face::South].iter() {
+            let offset = face.offset();
+            let neighbour = ChunkPos::new(pos.x + offset.x, pos.y + offset.y);
+            if !self.is_loaded(neighbour) {
+                continue;
+            }
+            for (chunk_pos, offset) in [(pos, offset), (neighbour, -offset)].iter() {
+                // Column of blocks facing the other chunk, skipping sub-chunks that are all air
+                let origin = chunk_pos.origin();
+                let edge = |o: PosScalar, v: PosScalar| if o > 0 { v + size - 1 } else { v };
+                for index in 0..config::BUILD_LIMIT as PosScalar {
+                    if self.loaded_chunk(*chunk_pos).unwrap().subchunk(chunk_pos.subchunk(index)).is_none() {
+                        continue;
+                    }
+                    for z in index * size..(index + 1) * size {
+                        for i in 0..size {
+                            let pos = if offset.x != 0 {
+                                BlockPos::new(edge(offset.x, origin.x), origin.y + i, z)
+                            } else {
+                                BlockPos::new(origin.x + i, edge(offset.y, origin.y), z)
+                            };
+                            self.refresh_visibility(pos, &registry);
+                        }
+                    }
+                }
             }
         }
+    }
 
-        return Box::new(Map{
-            loaded_chunks : chunks.into_boxed_slice(),
-        }); }
+    // In bytes, None keeps only the chunks tickets need.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        return self.memory_budget;
+    }
+
+    pub fn stats(&self) -> ChunkCacheStats {
+        return self.stats;
+    }
+
+    // Only affects chunks generated from now on, resident ones are kept.
+    pub fn set_generator(&mut self, generator: Arc<dyn WorldGenerator>, seed: u64) {
+        self.generator = generator;
+        self.seed = seed;
+    }
+
+    pub fn generator(&self) -> &Arc<dyn WorldGenerator> {
+        return &self.generator;
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Chunks being loaded or generated.
+    pub fn pending_loads(&self) -> usize {
+        return self.loads.pending_count();
+    }
 
-    fn pos_to_idx(pos: Vector2<PosScalar>) -> usize {
-        let signed_map_size = config::MAP_SIZE as PosScalar;
-        let abs_x = signed_map_size + pos.x;
-        let abs_y = signed_map_size - pos.y;
-        assert!(abs_x >= 0);
-        assert!(abs_y >= 0);
-        return (abs_y * signed_map_size + abs_x) as usize
+    pub fn add_ticket(&mut self, ticket: Ticket) -> TicketId {
+        return self.tickets.add(ticket);
     }
 
-    pub fn loaded_chunk(&self, pos: Vector2<PosScalar>) -> Option<&chunk::Chunk> {
-        return Some(&self.loaded_chunks[Map::pos_to_idx(pos)]);
+    pub fn move_ticket(&mut self, id: TicketId, pos: ChunkPos) {
+        if let Some(ticket) = self.tickets.get_mut(id) {
+            ticket.pos = pos;
+        }
+    }
+
+    pub fn remove_ticket(&mut self, id: TicketId) -> Option<Ticket> {
+        return self.tickets.remove(id);
+    }
+
+    pub fn tickets(&self) -> &TicketSet {
+        return &self.tickets;
+    }
+
+    pub fn tickets_mut(&mut self) -> &mut TicketSet {
+        return &mut self.tickets;
+    }
+
+    // None for chunks that aren't loaded, or are resident without a ticket.
+    pub fn load_level(&self, pos: ChunkPos) -> Option<LoadLevel> {
+        if !self.loaded_chunks.contains_key(&pos) {
+            return None;
+        }
+        return self.levels.get(&pos).copied();
+    }
+
+    // Loaded chunks at the level or above it.
+    pub fn chunks_at(&self, level: LoadLevel) -> impl Iterator<Item = &chunk::Chunk> {
+        let levels = &self.levels;
+        return self.chunks().filter(move |c| levels.get(&c.pos()).is_some_and(|l| *l >= level));
     }
-}
\ No newline at end of file
+
+    // Recomputes the load levels, takes in the chunks that finished loading, requests the missing ones
+    // and evicts the ones no ticket needs, saving dirty ones first. Loads that are no longer needed
+    // are cancelled, chunks the source can't store stay resident.
+    // Each failed load is reported once, by one of the updates after it finished. The chunk isn't
+    // requested again until the load levels change, so a broken chunk doesn't fail every update.
+    pub fn update_chunks<S: ChunkSource + 'static>(&mut self, source: &Arc<S>) -> Result<(), WorldError> {
+        self.clock += 1;
+        let previous = std::mem::replace(&mut self.levels, self.tickets.levels());
+        if self.levels != previous {
+            self.failed_loads.clear();
+        }
+
+        for (pos, result) in self.loads.poll() {
+            match result {
+                Ok(chunk) => { self.insert_chunk(chunk); }
+                Err(error) => {
+                    self.failed_loads.insert(pos);
+                    self.load_errors.push_back(error);
+                }
+            }
+        }
+
+        for pos in self.levels.keys() {
+            if self.loaded_chunks.contains_key(pos) {
+                if !previous.contains_key(pos) {
+                    self.stats.hits += 1;
+                }
+                continue;
+            }
+            if self.failed_loads.contains(pos) {
+                continue;
+            }
+            let (pos, source, generator, seed) = (*pos, source.clone(), self.generator.clone(), self.seed);
+            // Reading can't be interrupted, but generating is skipped once the load is cancelled.
+            // A panic fails the load like an error, instead of leaving the chunk missing without a word.
+            let requested = self.loads.submit(pos, move |token| {
+                let load = || match source.load_chunk(pos) {
+                    Ok(Some(chunk)) => Some(Ok(chunk)),
+                    Ok(None) if token.is_cancelled() => None,
+                    Ok(None) => Some(Ok(chunk::Chunk::generate(pos, seed, generator.as_ref()))),
+                    Err(error) => Some(Err(error)),
+                };
+                return panic::catch_unwind(AssertUnwindSafe(load)).unwrap_or(Some(Err(WorldError::LoadPanicked(pos))));
+            });
+            if requested {
+                self.stats.misses += 1;
+            }
+        }
+        let unneeded: Vec<ChunkPos> = self.loads.pending().filter(|p| !self.levels.contains_key(p)).copied().collect();
+        for pos in unneeded {
+            self.loads.cancel(&pos);
+        }
+
+        let mut resident_bytes = 0;
+        for (pos, resident) in self.loaded_chunks.iter_mut() {
+            resident.bytes = resident.chunk.memory_usage();
+            resident_bytes += resident.bytes;
+            if self.levels.contains_key(pos) {
+                resident.last_used = self.clock;
+            }
+        }
+        self.stats.resident_bytes = resident_bytes;
+        // A failed load is reported before a failed save, the chunk that failed to save is tried again
+        let saved = self.evict(source.as_ref());
+        return match self.load_errors.pop_front() {
+            Some(error) => Err(error),
+            None => saved,
+        };
+    }
+
+    // Same as update_chunks, but blocks until every chunk the tickets need is resident.
+    pub fn wait_for_chunks<S: ChunkSource + 'static>(&mut self, source: &Arc<S>) -> Result<(), WorldError> {
+        self.update_chunks(source)?;
+        while self.loads.pending_count() > 0 {
+            self.loads.wait();
+            self.update_chunks(source)?;
+        }
+        return Ok(());
+    }
+
+    fn evict<S: ChunkSource>(&mut self, source: &S) -> Result<(), WorldError> {
+        let mut candidates: Vec<(u64, ChunkPos)> = self.loaded_chunks.iter()
+            .filter(|(pos, _)| !self.levels.contains_key(pos))
+            .map(|(pos, resident)| (resident.last_used, *pos))
+            .collect();
+        candidates.sort();
+        for (_, pos) in candidates {
+            if self.memory_budget.is_some_and(|budget| self.stats.resident_bytes <= budget) {
+                break;
+            }
+            let resident = &self.loaded_chunks[&pos];
+            if resident.chunk.is_dirty() && !source.save_chunk(&resident.chunk)? {
+                continue;
+            }
+            self.stats.resident_bytes -= resident.bytes;
+            self.stats.evictions += 1;
+            self.loaded_chunks.remove(&pos);
+        }
+        return Ok(());
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        return self.chunks().map(|c| c.memory_usage()).sum();
+    }
+
+    pub fn dirty_chunks(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        return self.chunks().filter(|c| c.is_dirty()).map(|c| c.pos());
+    }
+
+    pub fn changed_since(&self, revision: u64) -> impl Iterator<Item = SubChunkPos> + '_ {
+        return self.chunks().flat_map(move |c| c.changed_since(revision));
+    }
+
+    pub fn block(&self, pos: BlockPos) -> Option<&block::Block> {
+        return self.loaded_chunk(pos.chunk())?.block(pos);
+    }
+
+    // Neighbours in other chunks get their visibility updated here,
+    // everything within the chunk is handled by Chunk::set_block.
+    pub fn set_block(&mut self, pos: BlockPos, block: block::Block, registry: &BlockRegistry) {
+        let chunk_pos = pos.chunk();
+        match self.loaded_chunk_mut(chunk_pos) {
+            Some(chunk) => { chunk.set_block(pos, block, registry); }
+            None => { return; }
+        }
+
+        let mut on_border = false;
+        for face in block::Face::ALL.iter() {
+            let neighbour = pos + face.offset();
+            if neighbour.chunk() != chunk_pos {
+                self.refresh_visibility(neighbour, registry);
+                on_border = true;
+            }
+        }
+        if on_border {
+            self.refresh_visibility(pos, registry);
+        }
+    }
+
+    // Blocks of all loaded chunks inside of the box, chunk by chunk.
+    pub fn blocks_in(&self, bounds: BlockBox) -> impl Iterator<Item = (BlockPos, &block::Block)> {
+        return bounds.chunks()
+            .filter_map(move |pos| self.loaded_chunk(pos))
+            .flat_map(move |chunk| chunk.blocks_in(bounds));
+    }
+
+    // Same as blocks_in, with chunks split between threads.
+    pub fn par_blocks_in(&self, bounds: BlockBox) -> impl ParallelIterator<Item = (BlockPos, &block::Block)> {
+        let chunks: Vec<&chunk::Chunk> = bounds.chunks().filter_map(|pos| self.loaded_chunk(pos)).collect();
+        return chunks.into_par_iter().flat_map_iter(move |chunk| chunk.blocks_in(bounds));
+    }
+
+    // Returning Some from the closure replaces the block, visibility across chunk borders is kept up to date.
+    pub fn update_blocks_in<F>(&mut self, bounds: BlockBox, registry: &BlockRegistry, mut f: F)
+        where F: FnMut(BlockPos, &block::Block) -> Option<block::Block> {
+        for chunk_pos in bounds.chunks() {
+            let clipped = match self.loaded_chunk(chunk_pos).and_then(|c| c.bounds().intersection(&bounds)) {
+                Some(clipped) => clipped,
+                None => { continue; }
+            };
+            for pos in clipped.positions() {
+                let old = *self.block(pos).unwrap();
+                if let Some(new) = f(pos, &old) {
+                    self.set_block(pos, new, registry);
+                }
+            }
+        }
+    }
+
+    fn is_opaque(&self, pos: BlockPos, registry: &BlockRegistry) -> bool {
+        return match self.block(pos) {
+            Some(block) => registry.properties(block).opaque,
+            None => false,
+        };
+    }
+
+    fn refresh_visibility(&mut self, pos: BlockPos, registry: &BlockRegistry) {
+        let block = match self.block(pos) {
+            Some(block) => *block,
+            None => { return; }
+        };
+        let mask = block::visible_faces(&block, |face| self.is_opaque(pos + face.offset(), registry));
+        if let Some(chunk) = self.loaded_chunk_mut(pos.chunk()) {
+            chunk.set_visible_mask(pos, mask);
+        }
+    }
+
+    // Only chunks that are at least ticking.
+    pub fn tick_block_entities(&mut self, dt: std::time::Duration) {
+        let levels = &self.levels;
+        for resident in self.loaded_chunks.values_mut().filter(|r| levels.get(&r.chunk.pos()).is_some_and(|l| *l >= LoadLevel::Ticking)) {
+            resident.chunk.tick_block_entities(dt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block_entity::{BlockEntity, ItemStack};
+    use crate::model::ticket::TicketKind;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // Keeps saved chunks in memory, or refuses to store them at all. Broken sources fail every
+    // load and save, blocked ones hold loads until they're unblocked.
+    #[derive(Default)]
+    struct MemorySource {
+        stored: Mutex<HashMap<ChunkPos, chunk::Chunk>>,
+        read_only: AtomicBool,
+        broken: AtomicBool,
+        blocked: AtomicBool,
+        loads: AtomicUsize,
+    }
+
+    impl MemorySource {
+        fn stored(&self) -> usize {
+            return self.stored.lock().unwrap().len();
+        }
+    }
+
+    impl ChunkSource for MemorySource {
+        fn load_chunk(&self, pos: ChunkPos) -> Result<Option<chunk::Chunk>, WorldError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            while self.blocked.load(Ordering::SeqCst) {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(WorldError::Level("load".to_string()));
+            }
+            return Ok(self.stored.lock().unwrap().get(&pos).cloned());
+        }
+
+        fn save_chunk(&self, chunk: &chunk::Chunk) -> Result<bool, WorldError> {
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(WorldError::Level("save".to_string()));
+            }
+            if self.read_only.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            let mut chunk = chunk.clone();
+            chunk.clear_dirty();
+            self.stored.lock().unwrap().insert(chunk.pos(), chunk);
+            return Ok(true);
+        }
+    }
+
+    fn map_around(pos: ChunkPos, radius: PosScalar) -> Box<Map> {
+        let mut map = Map::new();
+        map.add_ticket(Ticket::new(TicketKind::Plugin, pos, radius));
+        map.wait_for_chunks(&Arc::new(MemorySource::default())).unwrap();
+        return map;
+    }
+
+    #[test]
+    fn chunks_follow_tickets() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let source = Arc::new(MemorySource::default());
+        let mut map = Map::new();
+        assert!(map.block(BlockPos::new(0, 0, 0)).is_none());
+
+        let ticket = map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 1));
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.loaded_count(), 9);
+        assert_eq!(source.loads.load(Ordering::SeqCst), 9);
+        assert_eq!(map.load_level(ChunkPos::new(1, 1)), Some(LoadLevel::Border));
+        assert_eq!(map.chunks_at(LoadLevel::Ticking).count(), 0);
+        let pos = BlockPos::new(20, 20, 5);
+        map.set_block(pos, stone, &registry);
+
+        // Far away chunks work the same, the dirty one is saved when it's left behind
+        let far = ChunkPos::new(1_000_000, -1_000_000);
+        map.move_ticket(ticket, far);
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.loaded_count(), 9);
+        assert!(map.loaded_chunk(ChunkPos::new(1, 1)).is_none());
+        assert!(map.block(pos).is_none());
+        assert!(map.loaded_chunk(far).is_some());
+        assert_eq!(source.stored(), 1);
+
+        map.move_ticket(ticket, ChunkPos::new(0, 0));
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.block(pos).unwrap().block_id(), stone.block_id());
+
+        // Without anywhere to save them dirty chunks stay, clean ones are dropped
+        map.set_block(pos, block::Block::AIR, &registry);
+        source.read_only.store(true, Ordering::SeqCst);
+        map.remove_ticket(ticket);
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.loaded_count(), 1);
+        assert!(map.block(pos).unwrap().is_air());
+        assert_eq!(map.load_level(pos.chunk()), None);
+    }
+
+    #[test]
+    fn chunks_load_in_the_background() {
+        let source = Arc::new(MemorySource::default());
+        let mut map = Map::new();
+        let ticket = map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 1));
+        map.update_chunks(&source).unwrap();
+        map.update_chunks(&source).unwrap();
+        assert_eq!(map.stats().misses, 9);
+
+        // Results only arrive with an update, the ones no longer needed are dropped
+        map.move_ticket(ticket, ChunkPos::new(100, 0));
+        map.update_chunks(&source).unwrap();
+        assert_eq!(map.pending_loads(), 9);
+        assert!(!map.is_loaded(ChunkPos::new(0, 0)));
+        assert!(!map.is_loaded(ChunkPos::new(100, 0)));
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.loaded_count(), 9);
+        assert!(map.chunks().all(|c| (c.pos().x - 100).abs() <= 1));
+        assert_eq!(map.pending_loads(), 0);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let chunk_bytes = chunk::Chunk::empty(ChunkPos::new(0, 0)).memory_usage();
+        let source = Arc::new(MemorySource::default());
+        let mut map = Map::new();
+        let ticket = map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 0));
+        map.wait_for_chunks(&source).unwrap();
+        map.set_block(BlockPos::new(0, 0, 0), stone, &registry);
+        // Room for three chunks, one of them with a block
+        let stone_bytes = map.loaded_chunk(ChunkPos::new(0, 0)).unwrap().memory_usage();
+        map.set_memory_budget(Some(stone_bytes + 2 * chunk_bytes));
+
+        for x in 1..4 {
+            map.move_ticket(ticket, ChunkPos::new(x * 10, 0));
+            map.wait_for_chunks(&source).unwrap();
+        }
+        // The first chunk was saved on its way out, the other two are still cached
+        assert!(!map.is_loaded(ChunkPos::new(0, 0)));
+        assert!(source.stored.lock().unwrap().contains_key(&ChunkPos::new(0, 0)));
+        assert_eq!(map.loaded_count(), 3);
+        assert_eq!(map.stats().resident_bytes, 3 * chunk_bytes);
+
+        map.move_ticket(ticket, ChunkPos::new(10, 0));
+        map.wait_for_chunks(&source).unwrap();
+        map.move_ticket(ticket, ChunkPos::new(0, 0));
+        map.wait_for_chunks(&source).unwrap();
+        assert!(!map.is_loaded(ChunkPos::new(20, 0)));
+        assert_eq!(map.block(BlockPos::new(0, 0, 0)).unwrap().block_id(), stone.block_id());
+        assert_eq!(map.stats(), ChunkCacheStats{
+            hits: 1,
+            misses: 5,
+            evictions: 2,
+            resident_bytes: map.memory_usage(),
+        });
+
+        // Needed chunks stay no matter the budget
+        map.set_memory_budget(Some(0));
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.loaded_count(), 1);
+    }
+
+    // Counts the chunks it generates, or panics instead once told to
+    #[derive(Default)]
+    struct CountingGenerator {
+        generated: AtomicUsize,
+        panics: AtomicBool,
+    }
+
+    impl WorldGenerator for CountingGenerator {
+        fn settings(&self) -> crate::model::level::GeneratorSettings {
+            return Default::default();
+        }
+
+        fn surface_height(&self, _seed: u64, _x: PosScalar, _y: PosScalar) -> PosScalar {
+            return 0;
+        }
+
+        fn generate(&self, _seed: u64, _chunk: &mut chunk::Chunk) {
+            if self.panics.load(Ordering::SeqCst) {
+                panic!("generator failed");
+            }
+            self.generated.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn cancelled_loads_are_not_generated() {
+        let generator = Arc::new(CountingGenerator::default());
+        let source = Arc::new(MemorySource::default());
+        source.blocked.store(true, Ordering::SeqCst);
+        let mut map = Map::new();
+        map.set_generator(generator.clone(), 0);
+        let ticket = map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(9, 9), 0));
+        map.update_chunks(&source).unwrap();
+        while source.loads.load(Ordering::SeqCst) == 0 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        map.remove_ticket(ticket);
+        map.update_chunks(&source).unwrap();
+        source.blocked.store(false, Ordering::SeqCst);
+        // Waits for the running load
+        drop(map);
+        assert_eq!(generator.generated.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn failed_loads_are_reported_before_failed_saves() {
+        let registry = BlockRegistry::builtin();
+        let source = Arc::new(MemorySource::default());
+        let mut map = Map::new();
+        let ticket = map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 0));
+        map.wait_for_chunks(&source).unwrap();
+        map.set_block(BlockPos::new(0, 0, 0), registry.block("stone").unwrap(), &registry);
+        map.remove_ticket(ticket);
+        map.set_memory_budget(Some(0));
+
+        source.broken.store(true, Ordering::SeqCst);
+        map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(5, 5), 0));
+        assert!(matches!(map.update_chunks(&source), Err(WorldError::Level(message)) if message == "save"));
+        map.loads.wait();
+        assert!(matches!(map.update_chunks(&source), Err(WorldError::Level(message)) if message == "load"));
+        assert!(map.is_loaded(ChunkPos::new(0, 0)));
+    }
+
+    #[test]
+    fn failed_loads_are_reported_once() {
+        let source = Arc::new(MemorySource::default());
+        source.broken.store(true, Ordering::SeqCst);
+        let mut map = Map::new();
+        map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 1));
+        map.update_chunks(&source).unwrap();
+        while map.pending_loads() > 0 {
+            map.loads.wait();
+        }
+        // Nine failures, one per update, none of them requested again
+        for _ in 0..9 {
+            assert!(matches!(map.update_chunks(&source), Err(WorldError::Level(message)) if message == "load"));
+        }
+        map.update_chunks(&source).unwrap();
+        assert_eq!(map.pending_loads(), 0);
+        assert_eq!(source.loads.load(Ordering::SeqCst), 9);
+
+        // Changing the tickets tries again
+        source.broken.store(false, Ordering::SeqCst);
+        map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(5, 5), 0));
+        map.wait_for_chunks(&source).unwrap();
+        assert_eq!(map.loaded_count(), 10);
+    }
+
+    #[test]
+    fn panicking_generators_fail_the_load() {
+        let generator = Arc::new(CountingGenerator::default());
+        generator.panics.store(true, Ordering::SeqCst);
+        let source = Arc::new(MemorySource::default());
+        let mut map = Map::new();
+        map.set_generator(generator.clone(), 0);
+        map.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(3, 3), 0));
+        assert!(matches!(map.wait_for_chunks(&source), Err(WorldError::LoadPanicked(pos)) if pos == ChunkPos::new(3, 3)));
+        map.update_chunks(&source).unwrap();
+        assert_eq!(map.pending_loads(), 0);
+        assert!(!map.is_loaded(ChunkPos::new(3, 3)));
+    }
+
+    #[test]
+    fn only_ticking_chunks_are_simulated() {
+        let registry = BlockRegistry::builtin();
+        let furnace = registry.block("furnace").unwrap();
+        let mut map = Map::new();
+        map.add_ticket(Ticket::new(TicketKind::Forced, ChunkPos::new(0, 0), 0));
+        map.wait_for_chunks(&Arc::new(MemorySource::default())).unwrap();
+        assert_eq!(map.loaded_count(), 9);
+        assert_eq!(map.chunks_at(LoadLevel::Ticking).count(), 1);
+        assert_eq!(map.chunks_at(LoadLevel::EntityTicking).count(), 0);
+
+        let ticking = BlockPos::new(1, 1, 1);
+        let border = BlockPos::new(17, 1, 1);
+        for pos in [ticking, border].iter() {
+            map.set_block(*pos, furnace, &registry);
+            if let Some(BlockEntity::Furnace(data)) = map.loaded_chunk_mut(pos.chunk()).unwrap().block_entity_mut(*pos) {
+                data.input = Some(ItemStack::new("sand", 1));
+                data.fuel = Some(ItemStack::new("coal", 1));
+            }
+        }
+        map.tick_block_entities(std::time::Duration::from_secs(1));
+        let fuel = |map: &Map, pos: BlockPos| match map.loaded_chunk(pos.chunk()).unwrap().block_entity(pos) {
+            Some(BlockEntity::Furnace(data)) => data.fuel.clone(),
+            _ => None,
+        };
+        assert_eq!(fuel(&map, ticking), None);
+        assert_eq!(fuel(&map, border), Some(ItemStack::new("coal", 1)));
+    }
+
+    #[test]
+    fn chunks_hide_faces_of_their_neighbours() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let mut map = Map::new();
+        let (west, east) = (BlockPos::new(15, 3, 20), BlockPos::new(16, 3, 20));
+        for pos in [west, east].iter() {
+            let mut chunk = chunk::Chunk::empty(pos.chunk());
+            chunk.set_block(*pos, stone, &registry);
+            assert!(chunk.block(*pos).unwrap().is_face_visible(block::Face::East));
+            assert!(chunk.block(*pos).unwrap().is_face_visible(block::Face::West));
+            map.insert_chunk(chunk);
+        }
+        assert!(!map.block(west).unwrap().is_face_visible(block::Face::East));
+        assert!(map.block(west).unwrap().is_face_visible(block::Face::West));
+        assert!(!map.block(east).unwrap().is_face_visible(block::Face::West));
+        assert!(map.block(east).unwrap().is_face_visible(block::Face::East));
+    }
+
+    #[test]
+    fn border_visibility_is_not_saved() {
+        let registry = Arc::new(BlockRegistry::builtin());
+        let settings = crate::model::level::GeneratorSettings{ name: "flat".to_string(), ..Default::default() };
+        let generator = crate::model::generator::from_settings(&settings, &registry).unwrap();
+        let mut map = Map::new();
+        let before = chunk::current_revision();
+        map.insert_chunk(chunk::Chunk::generate(ChunkPos::new(0, 0), 1, generator.as_ref()));
+        map.insert_chunk(chunk::Chunk::generate(ChunkPos::new(1, 0), 1, generator.as_ref()));
+        assert!(!map.block(BlockPos::new(15, 3, 0)).unwrap().is_face_visible(block::Face::East));
+        assert!(!map.block(BlockPos::new(16, 3, 0)).unwrap().is_face_visible(block::Face::West));
+        // Meshes of both chunks are rebuilt, but they stay as generated on disk
+        assert_eq!(map.changed_since(before).count(), 2);
+        assert_eq!(map.dirty_chunks().count(), 0);
+    }
+
+    #[test]
+    fn iterates_across_chunk_borders() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.block("stone").unwrap();
+        let mut map = map_around(ChunkPos::new(0, 0), 2);
+        let bounds = BlockBox::new(BlockPos::new(-20, -3, 10), BlockPos::new(20, 3, 11));
+
+        map.update_blocks_in(bounds, &registry, |pos, _| if pos.x % 2 == 0 { Some(stone) } else { None });
+        let blocks: Vec<_> = map.blocks_in(bounds).map(|(p, b)| (p, *b)).collect();
+        assert_eq!(blocks.len(), bounds.volume());
+        assert!(blocks.iter().all(|(p, b)| bounds.contains(*p) && b.is_air() == (p.x % 2 != 0)));
+
+        // Faces toward the neighbour in the next chunk are hidden.
+        let border = map.block(BlockPos::new(0, 0, 10)).unwrap();
+        assert!(!border.is_face_visible(block::Face::North));
+        assert!(border.is_face_visible(block::Face::Down));
+
+        let parallel: Vec<_> = map.par_blocks_in(bounds).map(|(p, b)| (p, *b)).collect();
+        assert_eq!(parallel, blocks);
+    }
+
+    #[test]
+    fn empty_map_is_small() {
+        let map = map_around(ChunkPos::new(-32, 20), config::VIEW_RADIUS as PosScalar);
+        assert!(map.memory_usage() < 16 * 1024 * 1024);
+        assert!(map.block(BlockPos::new(-500, 300, 20)).unwrap().is_air());
+    }
+}
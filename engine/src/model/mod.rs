@@ -1,6 +1,17 @@
 pub mod config;
 pub mod block;
+pub mod block_state;
+pub mod block_entity;
+pub mod registry;
+pub mod palette;
+pub mod heightmap;
+pub mod biome;
+pub mod light;
+pub mod entity;
+pub mod level;
 pub mod chunk;
+pub mod generator;
+pub mod ticket;
 pub mod map;
 pub mod world;
 
@@ -0,0 +1,329 @@
+use crate::model::block::Block;
+
+use std::mem::size_of;
+
+const WORD_BITS: usize = 64;
+
+// Fixed size array of unsigned values of `bits` width, values don't cross word boundaries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitArray {
+    bits: u8,
+    len: usize,
+    words: Vec<u64>,
+}
+
+impl BitArray {
+    pub fn new(bits: u8, len: usize) -> Self {
+        assert!(bits > 0 && bits as usize <= WORD_BITS);
+        let per_word = WORD_BITS / bits as usize;
+        return BitArray{ bits, len, words: vec![0; len.div_ceil(per_word)] };
+    }
+
+    pub fn from_words(bits: u8, len: usize, words: Vec<u64>) -> Option<Self> {
+        if bits == 0 || bits as usize > WORD_BITS {
+            return None;
+        }
+        let array = BitArray::new(bits, len);
+        if array.words.len() != words.len() {
+            return None;
+        }
+        return Some(BitArray{ words, ..array });
+    }
+
+    pub fn bits(&self) -> u8 {
+        return self.bits;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn words(&self) -> &[u64] {
+        return &self.words;
+    }
+
+    fn locate(&self, idx: usize) -> (usize, usize) {
+        let per_word = WORD_BITS / self.bits as usize;
+        return (idx / per_word, (idx % per_word) * self.bits as usize);
+    }
+
+    fn mask(&self) -> u64 {
+        return u64::MAX >> (WORD_BITS - self.bits as usize);
+    }
+
+    pub fn get(&self, idx: usize) -> usize {
+        assert!(idx < self.len);
+        let (word, shift) = self.locate(idx);
+        return ((self.words[word] >> shift) & self.mask()) as usize;
+    }
+
+    pub fn set(&mut self, idx: usize, value: usize) {
+        assert!(idx < self.len);
+        let mask = self.mask();
+        debug_assert!(value as u64 <= mask);
+        let (word, shift) = self.locate(idx);
+        self.words[word] = (self.words[word] & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+}
+
+fn bits_for(entries: usize) -> u8 {
+    let mut bits = 1;
+    while (1usize << bits) < entries {
+        bits += 1;
+    }
+    return bits;
+}
+
+// Block storage with a per-container palette. A uniform container stores a single
+// palette entry and no index data, otherwise indices use as few bits as the palette needs.
+#[derive(Debug, Clone)]
+pub struct PalettedStorage {
+    palette: Vec<Block>,
+    counts: Vec<u32>,
+    // None for the single value case
+    indices: Option<BitArray>,
+    len: usize,
+}
+
+impl PalettedStorage {
+    pub fn new(len: usize, value: Block) -> Self {
+        return PalettedStorage{ palette: vec![value], counts: vec![len as u32], indices: None, len };
+    }
+
+    // Builds a storage from raw parts, e.g. when loading from disk. Returns None if inconsistent.
+    pub fn from_parts(palette: Vec<Block>, indices: Option<BitArray>, len: usize) -> Option<Self> {
+        // Counting and reusing entries relies on every value having a single one
+        if palette.iter().enumerate().any(|(idx, value)| palette[..idx].contains(value)) {
+            return None;
+        }
+        let mut counts = vec![0u32; palette.len()];
+        match indices.as_ref() {
+            Some(indices) => {
+                if indices.len() != len || bits_for(palette.len()) != indices.bits() {
+                    return None;
+                }
+                for idx in 0..len {
+                    *counts.get_mut(indices.get(idx))? += 1;
+                }
+            }
+            None => {
+                if palette.len() != 1 {
+                    return None;
+                }
+                counts[0] = len as u32;
+            }
+        }
+        let mut storage = PalettedStorage{ palette, counts, indices, len };
+        storage.compact_if_sparse();
+        return Some(storage);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn palette(&self) -> &[Block] {
+        return &self.palette;
+    }
+
+    pub fn indices(&self) -> Option<&BitArray> {
+        return self.indices.as_ref();
+    }
+
+    // The value of a uniform storage.
+    pub fn single_value(&self) -> Option<&Block> {
+        return match self.indices {
+            Some(_) => None,
+            None => Some(&self.palette[0]),
+        };
+    }
+
+    fn palette_index(&self, idx: usize) -> usize {
+        return match self.indices.as_ref() {
+            Some(indices) => indices.get(idx),
+            None => 0,
+        };
+    }
+
+    pub fn get(&self, idx: usize) -> &Block {
+        assert!(idx < self.len);
+        return &self.palette[self.palette_index(idx)];
+    }
+
+    // Returns whether the value changed.
+    pub fn set(&mut self, idx: usize, value: Block) -> bool {
+        assert!(idx < self.len);
+        let old = self.palette_index(idx);
+        if self.palette[old] == value {
+            return false;
+        }
+
+        let new = self.find_or_insert(value);
+        self.counts[old] -= 1;
+        self.counts[new] += 1;
+        self.indices.as_mut().unwrap().set(idx, new);
+        if self.counts[old] == 0 {
+            self.compact_if_sparse();
+        }
+        return true;
+    }
+
+    fn find_or_insert(&mut self, value: Block) -> usize {
+        if let Some(existing) = self.palette.iter().position(|b| *b == value) {
+            return existing;
+        }
+        if let Some(free) = self.counts.iter().position(|c| *c == 0) {
+            self.palette[free] = value;
+            return free;
+        }
+
+        self.palette.push(value);
+        self.counts.push(0);
+        let required_bits = bits_for(self.palette.len());
+        let current_bits = self.indices.as_ref().map_or(0, |i| i.bits());
+        if required_bits > current_bits {
+            self.repack(required_bits, None);
+        }
+        return self.palette.len() - 1;
+    }
+
+    // Rewrites indices with a new width, optionally remapping palette indices.
+    fn repack(&mut self, bits: u8, remap: Option<&[usize]>) {
+        let mut indices = BitArray::new(bits, self.len);
+        for idx in 0..self.len {
+            let old = self.palette_index(idx);
+            indices.set(idx, remap.map_or(old, |r| r[old]));
+        }
+        self.indices = Some(indices);
+    }
+
+    // Drops unused palette entries once the palette is mostly empty,
+    // and falls back to the single value form when only one entry is left.
+    fn compact_if_sparse(&mut self) {
+        let used = self.counts.iter().filter(|c| **c > 0).count();
+        if used == 1 {
+            let value = self.palette[self.counts.iter().position(|c| *c > 0).unwrap()];
+            *self = PalettedStorage::new(self.len, value);
+            return;
+        }
+        let capacity = self.indices.as_ref().map_or(1, |i| 1usize << i.bits());
+        if used * 4 > capacity {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::with_capacity(used);
+        let mut counts = Vec::with_capacity(used);
+        for (old, count) in self.counts.iter().enumerate() {
+            if *count > 0 {
+                remap[old] = palette.len();
+                palette.push(self.palette[old]);
+                counts.push(*count);
+            }
+        }
+        self.repack(bits_for(used), Some(&remap));
+        self.palette = palette;
+        self.counts = counts;
+    }
+
+    // Approximate heap memory used by this storage, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let indices = self.indices.as_ref().map_or(0, |i| i.words.capacity() * size_of::<u64>());
+        return self.palette.capacity() * size_of::<Block>() + self.counts.capacity() * size_of::<u32>() + indices;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block::BlockId;
+
+    fn block(id: u16) -> Block {
+        return Block::with_id(BlockId::new(id).unwrap());
+    }
+
+    #[test]
+    fn bit_array_round_trips() {
+        for bits in [1u8, 3, 5, 13, 64].iter() {
+            let mut array = BitArray::new(*bits, 100);
+            let max = if *bits == 64 { usize::MAX } else { (1usize << bits) - 1 };
+            for idx in 0..100 {
+                array.set(idx, (idx * 7919) & max);
+            }
+            for idx in 0..100 {
+                assert_eq!(array.get(idx), (idx * 7919) & max);
+            }
+        }
+        assert!(BitArray::from_words(5, 100, vec![0; 3]).is_none());
+        assert!(BitArray::from_words(5, 100, vec![0; 9]).is_some());
+    }
+
+    #[test]
+    fn palette_grows_and_shrinks() {
+        let mut storage = PalettedStorage::new(4096, Block::new());
+        assert!(storage.single_value().is_some());
+        assert!(!storage.set(10, Block::new()));
+
+        for idx in 0..40 {
+            assert!(storage.set(idx, block(idx as u16 + 1)));
+        }
+        assert_eq!(storage.palette().len(), 41);
+        assert_eq!(storage.indices().unwrap().bits(), 6);
+        for idx in 0..40 {
+            assert_eq!(*storage.get(idx), block(idx as u16 + 1));
+        }
+        assert_eq!(*storage.get(40), Block::new());
+
+        // Removing most of the values compacts the palette.
+        for idx in 3..40 {
+            storage.set(idx, Block::new());
+        }
+        assert_eq!(storage.palette().len(), 4);
+        assert_eq!(storage.indices().unwrap().bits(), 2);
+        assert_eq!(*storage.get(2), block(3));
+
+        for idx in 0..3 {
+            storage.set(idx, block(7));
+        }
+        for idx in 0..3 {
+            storage.set(idx, Block::new());
+        }
+        assert_eq!(storage.single_value(), Some(&Block::new()));
+    }
+
+    #[test]
+    fn uniform_storage_is_small() {
+        let uniform = PalettedStorage::new(4096, block(1));
+        assert!(uniform.memory_usage() < 64);
+
+        let mut mixed = uniform.clone();
+        for idx in (0..4096).step_by(2) {
+            mixed.set(idx, block(2));
+        }
+        // One bit per block plus the palette.
+        assert!(mixed.memory_usage() <= 4096 / 8 + 64);
+        assert!(mixed.memory_usage() < 4096 * size_of::<Block>() / 16);
+    }
+
+    #[test]
+    fn from_parts_validates() {
+        let mut indices = BitArray::new(1, 4);
+        indices.set(1, 1);
+        let storage = PalettedStorage::from_parts(vec![block(1), block(2)], Some(indices.clone()), 4).unwrap();
+        assert_eq!(*storage.get(1), block(2));
+        assert_eq!(*storage.get(2), block(1));
+
+        assert!(PalettedStorage::from_parts(vec![block(1), block(2)], Some(BitArray::new(2, 4)), 4).is_none());
+        assert!(PalettedStorage::from_parts(vec![block(1), block(2), block(3)], Some(indices), 4).is_none());
+        assert!(PalettedStorage::from_parts(vec![], None, 4).is_none());
+        assert!(PalettedStorage::from_parts(vec![block(1), block(1)], Some(BitArray::new(1, 4)), 4).is_none());
+    }
+}
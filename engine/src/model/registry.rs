@@ -0,0 +1,245 @@
+use crate::model::block::{Block, BlockId};
+use crate::model::block_entity::BlockEntityKind;
+use crate::model::block_state::{BlockStateDescriptor, PropertyDefinition, StateError, StateLayout};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+const BUILTIN_DEFINITIONS: &str = include_str!("../../assets/blocks.ron");
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    Parse(String),
+    MissingAir,
+    DuplicateName(String),
+    TooManyBlocks(usize),
+    State(String, StateError),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BlockTextures {
+    pub all: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub side: Option<String>,
+}
+
+impl BlockTextures {
+    pub fn top(&self) -> Option<&str> {
+        return self.top.as_ref().or(self.all.as_ref()).map(|s| s.as_str());
+    }
+
+    pub fn bottom(&self) -> Option<&str> {
+        return self.bottom.as_ref().or(self.all.as_ref()).map(|s| s.as_str());
+    }
+
+    pub fn side(&self) -> Option<&str> {
+        return self.side.as_ref().or(self.all.as_ref()).map(|s| s.as_str());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BlockDefinition {
+    pub name: String,
+    pub solid: bool,
+    pub opaque: bool,
+    pub fluid: bool,
+    pub light_emission: u8,
+    // Negative hardness means the block can't be broken.
+    pub hardness: f32,
+    pub friction: f32,
+    pub textures: BlockTextures,
+    pub properties: Vec<PropertyDefinition>,
+    pub block_entity: Option<BlockEntityKind>,
+}
+
+impl Default for BlockDefinition {
+    fn default() -> Self {
+        return BlockDefinition{
+            name: String::new(),
+            solid: true,
+            opaque: true,
+            fluid: false,
+            light_emission: 0,
+            hardness: 1.0,
+            friction: 0.6,
+            textures: BlockTextures::default(),
+            properties: Vec::new(),
+            block_entity: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    definitions: Vec<BlockDefinition>,
+    layouts: Vec<StateLayout>,
+    ids: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    pub fn builtin() -> Self {
+        return BlockRegistry::from_ron(BUILTIN_DEFINITIONS).expect("invalid builtin block definitions");
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RegistryError> {
+        let source = std::fs::read_to_string(path).map_err(RegistryError::Io)?;
+        return BlockRegistry::from_ron(&source);
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, RegistryError> {
+        let definitions: Vec<BlockDefinition> = ron::de::from_str(source)
+            .map_err(|e| RegistryError::Parse(e.to_string()))?;
+        return BlockRegistry::from_definitions(definitions);
+    }
+
+    // Ids are assigned in definition order, the first definition has to be air.
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<Self, RegistryError> {
+        match definitions.first() {
+            Some(air) if air.name == "air" => {}
+            _ => { return Err(RegistryError::MissingAir); }
+        }
+        if definitions.len() > BlockId::COUNT {
+            return Err(RegistryError::TooManyBlocks(definitions.len()));
+        }
+
+        let mut ids = HashMap::new();
+        let mut layouts = Vec::with_capacity(definitions.len());
+        for (idx, definition) in definitions.iter().enumerate() {
+            let id = BlockId::new(idx as u16).unwrap();
+            if ids.insert(definition.name.clone(), id).is_some() {
+                return Err(RegistryError::DuplicateName(definition.name.clone()));
+            }
+            let layout = StateLayout::new(definition.properties.clone())
+                .map_err(|e| RegistryError::State(definition.name.clone(), e))?;
+            layouts.push(layout);
+        }
+
+        return Ok(BlockRegistry{ definitions, layouts, ids });
+    }
+
+    pub fn len(&self) -> usize {
+        return self.definitions.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.definitions.is_empty();
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        return self.ids.get(name).copied();
+    }
+
+    pub fn definition(&self, id: BlockId) -> Option<&BlockDefinition> {
+        return self.definitions.get(id.index());
+    }
+
+    // Unknown ids resolve to air, so lookups from chunk data never fail.
+    pub fn properties(&self, block: &Block) -> &BlockDefinition {
+        return self.definition(block.block_id()).unwrap_or(&self.definitions[BlockId::AIR.index()]);
+    }
+
+    pub fn layout(&self, id: BlockId) -> &StateLayout {
+        return self.layouts.get(id.index()).unwrap_or(&self.layouts[BlockId::AIR.index()]);
+    }
+
+    // The block with the given name in its default state.
+    pub fn block(&self, name: &str) -> Option<Block> {
+        return self.id(name).map(Block::with_id);
+    }
+
+    pub fn describe(&self, block: &Block) -> BlockStateDescriptor {
+        let name = self.properties(block).name.clone();
+        let properties = self.layout(block.block_id()).values(block.state())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        return BlockStateDescriptor{ name, properties };
+    }
+
+    pub fn resolve(&self, descriptor: &BlockStateDescriptor) -> Result<Block, StateError> {
+        let id = self.id(&descriptor.name).ok_or_else(|| StateError::UnknownBlock(descriptor.name.clone()))?;
+        let mut block = Block::with_id(id);
+        block.set_state(self.layout(id).state_from(&descriptor.properties)?);
+        return Ok(block);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &BlockDefinition)> {
+        return self.definitions.iter().enumerate().map(|(idx, def)| (BlockId::new(idx as u16).unwrap(), def));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_registry_loads() {
+        let registry = BlockRegistry::builtin();
+        assert_eq!(registry.id("air"), Some(BlockId::AIR));
+        let air = registry.properties(&Block::new());
+        assert!(!air.solid && !air.opaque);
+
+        let grass = registry.id("grass_block").unwrap();
+        let def = registry.definition(grass).unwrap();
+        assert_eq!(def.textures.top(), Some("grass_top"));
+        assert_eq!(def.textures.bottom(), Some("dirt"));
+
+        let glowstone = registry.definition(registry.id("glowstone").unwrap()).unwrap();
+        assert_eq!(glowstone.light_emission, 15);
+
+        let chest = registry.definition(registry.id("chest").unwrap()).unwrap();
+        assert_eq!(chest.block_entity, Some(BlockEntityKind::Chest));
+    }
+
+    #[test]
+    fn block_states_round_trip_through_descriptors() {
+        let registry = BlockRegistry::builtin();
+        let lever = registry.block("lever").unwrap();
+        assert_eq!(lever.get(&registry, "facing"), Some("north"));
+        assert_eq!(lever.get(&registry, "powered"), Some("false"));
+
+        let lever = lever.with(&registry, "facing", "west").unwrap().with(&registry, "powered", "true").unwrap();
+        assert_eq!(lever.block_id(), registry.id("lever").unwrap());
+        assert_eq!(lever.get(&registry, "facing"), Some("west"));
+        assert_eq!(lever.get(&registry, "powered"), Some("true"));
+        assert_eq!(lever.get(&registry, "axis"), None);
+        assert!(lever.with(&registry, "facing", "up").is_err());
+
+        for (id, _) in registry.iter() {
+            for state in 0..registry.layout(id).state_count() {
+                let mut block = Block::with_id(id);
+                block.set_state(state as u16);
+                assert_eq!(registry.resolve(&registry.describe(&block)), Ok(block));
+            }
+        }
+
+        let mut descriptor = registry.describe(&lever);
+        descriptor.properties.insert("removed".to_string(), "x".to_string());
+        descriptor.properties.remove("powered");
+        assert_eq!(registry.resolve(&descriptor).unwrap().get(&registry, "powered"), Some("false"));
+        descriptor.name = "missing".to_string();
+        assert!(registry.resolve(&descriptor).is_err());
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        assert!(matches!(BlockRegistry::from_ron(r#"[(name: "stone")]"#), Err(RegistryError::MissingAir)));
+        assert!(matches!(
+            BlockRegistry::from_ron(r#"[(name: "air"), (name: "stone"), (name: "stone")]"#),
+            Err(RegistryError::DuplicateName(_))));
+        assert!(matches!(BlockRegistry::from_ron("[(name: 1)]"), Err(RegistryError::Parse(_))));
+        assert!(matches!(
+            BlockRegistry::from_ron(r#"[(name: "air"), (name: "lever", properties: [(name: "powered", values: [])])]"#),
+            Err(RegistryError::State(_, StateError::EmptyProperty(_)))));
+
+        let mut definitions = vec![BlockDefinition{ name: "air".to_string(), ..Default::default() }];
+        for idx in 0..BlockId::COUNT {
+            definitions.push(BlockDefinition{ name: format!("block_{}", idx), ..Default::default() });
+        }
+        assert!(matches!(BlockRegistry::from_definitions(definitions), Err(RegistryError::TooManyBlocks(_))));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::core::*;
+
+use std::collections::HashMap;
+
+pub type TicketId = u32;
+
+// How much of the simulation runs in a chunk, each level includes the ones before it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LoadLevel {
+    // Loaded so that neighbours can see it, nothing runs
+    Border,
+    // Blocks and block entities are updated
+    Ticking,
+    // Entities are updated as well
+    EntityTicking,
+}
+
+impl LoadLevel {
+    pub const ALL: [LoadLevel; 3] = [LoadLevel::Border, LoadLevel::Ticking, LoadLevel::EntityTicking];
+
+    pub fn index(&self) -> usize {
+        return *self as usize;
+    }
+
+    fn lower(&self, steps: PosScalar) -> Option<LoadLevel> {
+        let index = self.index() as PosScalar - steps;
+        if index < 0 {
+            return None;
+        }
+        return Some(LoadLevel::ALL[index as usize]);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TicketKind {
+    Player,
+    Spawn,
+    // Machines and anything else the server keeps running without players around
+    Forced,
+    // Pinned by a plugin
+    Plugin,
+}
+
+impl TicketKind {
+    pub fn default_level(&self) -> LoadLevel {
+        return match self {
+            TicketKind::Player => LoadLevel::EntityTicking,
+            TicketKind::Spawn => LoadLevel::EntityTicking,
+            TicketKind::Forced => LoadLevel::Ticking,
+            TicketKind::Plugin => LoadLevel::Border,
+        };
+    }
+}
+
+// Chunks within `radius` of `pos` get `level`, distances are measured per axis. Every ring
+// further out gets one level less, so the ticking area is surrounded by loaded chunks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    pub kind: TicketKind,
+    pub pos: ChunkPos,
+    pub radius: PosScalar,
+    pub level: LoadLevel,
+    pub expires_at: Option<u64>, // In milliseconds of game time, see LevelData::time
+}
+
+impl Ticket {
+    pub fn new(kind: TicketKind, pos: ChunkPos, radius: PosScalar) -> Self {
+        return Ticket{ kind, pos, radius, level: kind.default_level(), expires_at: None };
+    }
+
+    pub fn level_at(&self, pos: ChunkPos) -> Option<LoadLevel> {
+        let distance = (pos.x - self.pos.x).abs().max((pos.y - self.pos.y).abs());
+        return self.level.lower((distance - self.radius).max(0));
+    }
+
+    // Radius of the area where the ticket gives any level at all.
+    pub fn extent(&self) -> PosScalar {
+        return self.radius + self.level.index() as PosScalar;
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TicketSet {
+    tickets: HashMap<TicketId, Ticket>,
+    next_id: TicketId,
+}
+
+impl TicketSet {
+    pub fn new() -> Self {
+        return TicketSet::default();
+    }
+
+    pub fn add(&mut self, ticket: Ticket) -> TicketId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tickets.insert(id, ticket);
+        return id;
+    }
+
+    pub fn get(&self, id: TicketId) -> Option<&Ticket> {
+        return self.tickets.get(&id);
+    }
+
+    pub fn get_mut(&mut self, id: TicketId) -> Option<&mut Ticket> {
+        return self.tickets.get_mut(&id);
+    }
+
+    pub fn remove(&mut self, id: TicketId) -> Option<Ticket> {
+        return self.tickets.remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.tickets.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.tickets.is_empty();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TicketId, &Ticket)> {
+        return self.tickets.iter().map(|(id, ticket)| (*id, ticket));
+    }
+
+    // Returns how many tickets expired.
+    pub fn remove_expired(&mut self, now: u64) -> usize {
+        let before = self.tickets.len();
+        self.tickets.retain(|_, ticket| ticket.expires_at.is_none_or(|t| t > now));
+        return before - self.tickets.len();
+    }
+
+    // Highest level any ticket gives to each chunk, chunks without one aren't needed.
+    pub fn levels(&self) -> HashMap<ChunkPos, LoadLevel> {
+        let mut levels = HashMap::new();
+        for ticket in self.tickets.values() {
+            let extent = ticket.extent();
+            for y in ticket.pos.y - extent..=ticket.pos.y + extent {
+                for x in ticket.pos.x - extent..=ticket.pos.x + extent {
+                    let pos = ChunkPos::new(x, y);
+                    let level = ticket.level_at(pos).unwrap();
+                    let entry = levels.entry(pos).or_insert(level);
+                    *entry = (*entry).max(level);
+                }
+            }
+        }
+        return levels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_drop_outside_of_the_radius() {
+        let mut tickets = TicketSet::new();
+        let player = tickets.add(Ticket::new(TicketKind::Player, ChunkPos::new(0, 0), 1));
+        let levels = tickets.levels();
+        assert_eq!(levels.len(), 7 * 7);
+        assert_eq!(levels[&ChunkPos::new(1, -1)], LoadLevel::EntityTicking);
+        assert_eq!(levels[&ChunkPos::new(2, 0)], LoadLevel::Ticking);
+        assert_eq!(levels[&ChunkPos::new(-3, 3)], LoadLevel::Border);
+
+        // Overlapping tickets keep the highest level
+        let mut forced = Ticket::new(TicketKind::Forced, ChunkPos::new(4, 0), 0);
+        forced.expires_at = Some(1000);
+        tickets.add(forced);
+        let levels = tickets.levels();
+        assert_eq!(levels[&ChunkPos::new(2, 0)], LoadLevel::Ticking);
+        assert_eq!(levels[&ChunkPos::new(4, 0)], LoadLevel::Ticking);
+        assert_eq!(levels[&ChunkPos::new(5, 0)], LoadLevel::Border);
+        assert_eq!(levels.get(&ChunkPos::new(6, 0)), None);
+
+        assert_eq!(tickets.remove_expired(999), 0);
+        assert_eq!(tickets.remove_expired(1000), 1);
+        tickets.remove(player);
+        assert!(tickets.levels().is_empty());
+    }
+}
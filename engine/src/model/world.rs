@@ -1,12 +1,588 @@
-use super::map;
+use crate::core::*;
+use crate::io::fs::atomic;
+use crate::io::fs::chunk_format::{self, ChunkFormatError};
+use crate::io::fs::migration::{self, MigrationError};
+use crate::io::fs::region::{RegionError, RegionStore};
+use super::block::Block;
+use super::chunk::{self, Chunk};
+use super::config;
+use super::entity::player::PlayerData;
+use super::generator::{self, GeneratorError, WorldGenerator};
+use super::heightmap::HeightmapKind;
+use super::level::LevelData;
+use super::map::{self, ChunkSource};
+use super::registry::BlockRegistry;
+use super::ticket::{Ticket, TicketId, TicketKind};
+
+use rayon::iter::ParallelIterator;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// World directory layout:
+//   level.ron              LevelData
+//   level.ron.bak          the previous level.ron, used if level.ron is damaged
+//   region/r.<x>.<y>.rcr   chunks, see io::fs::region
+//   players/<name>.ron     PlayerData
+pub(crate) const LEVEL_FILE: &str = "level.ron";
+pub(crate) const LEVEL_BACKUP_FILE: &str = "level.ron.bak";
+pub(crate) const REGION_DIR: &str = "region";
+pub(crate) const PLAYERS_DIR: &str = "players";
+
+#[derive(Debug)]
+pub enum WorldError {
+    Io(std::io::Error),
+    NotAWorld(PathBuf),
+    Level(String),
+    UnsupportedVersion(u32),
+    Region(RegionError),
+    Chunk(ChunkPos, ChunkFormatError),
+    Player(String, String),
+    InvalidPlayerName(String),
+    Generator(GeneratorError),
+    // Loading or generating the chunk panicked
+    LoadPanicked(ChunkPos),
+}
+
+impl From<std::io::Error> for WorldError {
+    fn from(error: std::io::Error) -> Self {
+        return WorldError::Io(error);
+    }
+}
+
+impl From<RegionError> for WorldError {
+    fn from(error: RegionError) -> Self {
+        return WorldError::Region(error);
+    }
+}
+
+impl From<GeneratorError> for WorldError {
+    fn from(error: GeneratorError) -> Self {
+        return WorldError::Generator(error);
+    }
+}
+
+impl From<MigrationError> for WorldError {
+    fn from(error: MigrationError) -> Self {
+        return match error {
+            MigrationError::Io(error) => WorldError::Io(error),
+            MigrationError::Level(message) => WorldError::Level(message),
+            MigrationError::UnsupportedVersion(version) => WorldError::UnsupportedVersion(version),
+            MigrationError::Region(error) => WorldError::Region(error),
+        };
+    }
+}
+
+// Chunks of the world directory as seen by Map, shared with the threads loading them. Worlds that
+// were never saved have nowhere to put unloaded chunks, so their dirty chunks stay resident.
+struct WorldStorage {
+    registry: Arc<BlockRegistry>,
+    // Chunks of the directory the world was last loaded from or saved to, only dirty chunks need to be written there
+    store: Mutex<Option<RegionStore>>,
+}
+
+impl ChunkSource for WorldStorage {
+    // Only reading the data holds the lock, decoding happens outside of it.
+    fn load_chunk(&self, pos: ChunkPos) -> Result<Option<Chunk>, WorldError> {
+        let data = match self.store.lock().unwrap().as_mut() {
+            Some(store) => store.read_chunk(pos)?,
+            None => None,
+        };
+        let data = match data {
+            Some(data) => data,
+            None => { return Ok(None); }
+        };
+        let chunk = migration::migrate_chunk(data)
+            .and_then(|data| chunk_format::decode(&data, &self.registry))
+            .map_err(|e| WorldError::Chunk(pos, e))?;
+        if chunk.pos() != pos {
+            return Err(WorldError::Chunk(pos, ChunkFormatError::InvalidData(format!("stored as {:?}", chunk.pos()))));
+        }
+        return Ok(Some(chunk));
+    }
+
+    fn save_chunk(&self, chunk: &Chunk) -> Result<bool, WorldError> {
+        let data = chunk_format::encode(chunk, &self.registry);
+        return match self.store.lock().unwrap().as_mut() {
+            Some(store) => {
+                store.write_chunk(chunk.pos(), &data)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        };
+    }
+}
 
-#[derive(Clone)]
 pub struct World {
-    map: Box<map::Map>
+    registry: Arc<BlockRegistry>,
+    map: Box<map::Map>,
+    level: LevelData,
+    players: BTreeMap<String, PlayerData>,
+    // Tickets keeping the chunks around players that joined loaded
+    player_tickets: HashMap<String, TicketId>,
+    storage: Arc<WorldStorage>,
 }
 
 impl World {
-    pub fn new() -> Self { return World{map: map::Map::new()}; }
-    pub fn load(path: &str) -> Self { return World::new(); }
-    pub fn save(path: &str) {}
-}
\ No newline at end of file
+    pub fn new() -> Self { return World::with_registry(Arc::new(BlockRegistry::builtin())); }
+    pub fn with_registry(registry: Arc<BlockRegistry>) -> Self {
+        return World{
+            registry: registry.clone(),
+            map: map::Map::with_registry(registry.clone()),
+            level: LevelData::new(0),
+            players: BTreeMap::new(),
+            player_tickets: HashMap::new(),
+            storage: Arc::new(WorldStorage{ registry, store: Mutex::new(None) }),
+        };
+    }
+
+    // New world with the default generator, players spawn on the ground above the origin.
+    pub fn create(seed: u64) -> Self {
+        let mut world = World::new();
+        let generator = generator::from_settings(&generator::default_settings(), &world.registry)
+            .expect("builtin blocks are missing from the default generator");
+        world.level.seed = seed;
+        world.level.spawn = BlockPos::new(0, 0, generator.surface_height(seed, 0, 0));
+        world.set_generator(generator);
+        return world;
+    }
+
+    pub fn load(path: &Path) -> Result<Self, WorldError> {
+        return World::load_with_registry(path, Arc::new(BlockRegistry::builtin()));
+    }
+
+    // Chunks aren't read until a ticket needs them.
+    // Upgrades the world if the level file was written by an older version.
+    fn read_level(dir: &Path, file: &str) -> Result<LevelData, WorldError> {
+        return Ok(migration::migrate_level(dir, &std::fs::read_to_string(dir.join(file))?)?);
+    }
+
+    pub fn load_with_registry(path: &Path, registry: Arc<BlockRegistry>) -> Result<Self, WorldError> {
+        let level_path = path.join(LEVEL_FILE);
+        let backup_path = path.join(LEVEL_BACKUP_FILE);
+        if !level_path.is_file() && !backup_path.is_file() {
+            return Err(WorldError::NotAWorld(path.to_path_buf()));
+        }
+        // Only a level file that can't be read or parsed falls back to the backup. Worlds of a newer
+        // version or that failed to upgrade must not be overwritten by the older backup on the next save.
+        let level = match std::fs::read_to_string(&level_path) {
+            Ok(source) => match migration::migrate_level(path, &source) {
+                Err(MigrationError::Level(message)) => World::read_level(path, LEVEL_BACKUP_FILE).map_err(|_| WorldError::Level(message))?,
+                result => result?,
+            },
+            Err(error) => World::read_level(path, LEVEL_BACKUP_FILE).map_err(|_| WorldError::Io(error))?,
+        };
+
+        let mut world = World::with_registry(registry);
+        let generator = generator::from_settings(&level.generator, &world.registry)?;
+        world.map.set_generator(generator, level.seed);
+        world.level = level;
+        *world.storage.store.lock().unwrap() = Some(RegionStore::new(&path.join(REGION_DIR))?);
+
+        let players_dir = path.join(PLAYERS_DIR);
+        if players_dir.is_dir() {
+            for entry in std::fs::read_dir(players_dir)? {
+                let entry_path = entry?.path();
+                if entry_path.extension().is_none_or(|e| e != "ron") {
+                    continue;
+                }
+                let name = entry_path.file_stem().unwrap().to_string_lossy().to_string();
+                let player: PlayerData = ron::de::from_str(&std::fs::read_to_string(&entry_path)?)
+                    .map_err(|e| WorldError::Player(name.clone(), e.to_string()))?;
+                if !PlayerData::is_valid_name(&player.name) {
+                    return Err(WorldError::InvalidPlayerName(player.name));
+                }
+                if player.name != name {
+                    return Err(WorldError::Player(name, format!("the file holds player {}", player.name)));
+                }
+                world.players.insert(player.name.clone(), player);
+            }
+        }
+
+        return Ok(world);
+    }
+
+    // Creates the directory if needed. Saving to the directory the world came from
+    // only writes chunks that changed, a new directory gets the region files of the old one
+    // and every non empty loaded chunk.
+    // Chunks are committed before the level file, a crash in between leaves newer chunks
+    // with older metadata, but never a partially written file.
+    pub fn save(&mut self, path: &Path) -> Result<(), WorldError> {
+        // Names are checked when players join or are loaded
+        debug_assert!(self.players.values().all(|player| PlayerData::is_valid_name(&player.name)));
+        std::fs::create_dir_all(path)?;
+
+        let region_dir = path.join(REGION_DIR);
+        let mut storage = self.storage.store.lock().unwrap();
+        let full_save = storage.as_ref().map(|s| s.dir()) != Some(region_dir.as_path());
+        if full_save {
+            std::fs::create_dir_all(&region_dir)?;
+            // Chunks that aren't loaded only exist in the old directory
+            if let Some(old) = storage.as_mut() {
+                old.sync()?;
+                for entry in std::fs::read_dir(old.dir())? {
+                    let entry_path = entry?.path();
+                    if entry_path.is_file() {
+                        atomic::write_atomic(&region_dir.join(entry_path.file_name().unwrap()), &std::fs::read(&entry_path)?)?;
+                    }
+                }
+            }
+            *storage = Some(RegionStore::new(&region_dir)?);
+        }
+        let store = storage.as_mut().unwrap();
+        for chunk in self.map.chunks_mut() {
+            if chunk.is_dirty() || (full_save && !chunk.is_empty()) {
+                store.write_chunk(chunk.pos(), &chunk_format::encode(chunk, &self.registry))?;
+                chunk.clear_dirty();
+            }
+        }
+        store.sync()?;
+
+        self.level.last_played = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let level = ron::ser::to_string_pretty(&self.level, ron::ser::PrettyConfig::default())
+            .map_err(|e| WorldError::Level(e.to_string()))?;
+        let level_path = path.join(LEVEL_FILE);
+        if level_path.is_file() {
+            atomic::write_atomic(&path.join(LEVEL_BACKUP_FILE), &std::fs::read(&level_path)?)?;
+        }
+        atomic::write_atomic(&level_path, level.as_bytes())?;
+
+        let players_dir = path.join(PLAYERS_DIR);
+        std::fs::create_dir_all(&players_dir)?;
+        for player in self.players.values() {
+            let data = ron::ser::to_string_pretty(player, ron::ser::PrettyConfig::default())
+                .map_err(|e| WorldError::Player(player.name.clone(), e.to_string()))?;
+            atomic::write_atomic(&players_dir.join(format!("{}.ron", player.name)), data.as_bytes())?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn registry(&self) -> &BlockRegistry {
+        return &self.registry;
+    }
+
+    pub fn level(&self) -> &LevelData {
+        return &self.level;
+    }
+
+    pub fn level_mut(&mut self) -> &mut LevelData {
+        return &mut self.level;
+    }
+
+    // Chunks that were already generated keep their terrain. The settings are saved with the level,
+    // so loading the world brings back the same generator.
+    pub fn set_generator(&mut self, generator: Arc<dyn WorldGenerator>) {
+        self.level.generator = generator.settings();
+        self.map.set_generator(generator, self.level.seed);
+    }
+
+    pub fn advance_time(&mut self, dt: std::time::Duration) {
+        self.level.time += dt.as_millis() as u64;
+    }
+
+    pub fn player(&self, name: &str) -> Option<&PlayerData> {
+        return self.players.get(name);
+    }
+
+    pub fn player_mut(&mut self, name: &str) -> Option<&mut PlayerData> {
+        return self.players.get_mut(name);
+    }
+
+    // Creates the player at the spawn point if it doesn't exist yet. Chunks within
+    // VIEW_RADIUS of the player are kept loaded until it leaves.
+    pub fn join_player(&mut self, name: &str) -> Result<&mut PlayerData, WorldError> {
+        if !PlayerData::is_valid_name(name) {
+            return Err(WorldError::InvalidPlayerName(name.to_string()));
+        }
+        let spawn = self.level.spawn;
+        let player = self.players.entry(name.to_string())
+            .or_insert_with(|| PlayerData::new(name, [spawn.x as f64 + 0.5, spawn.y as f64 + 0.5, spawn.z as f64]));
+        if !self.player_tickets.contains_key(name) {
+            let ticket = Ticket::new(TicketKind::Player, player.block_pos().chunk(), config::VIEW_RADIUS as PosScalar);
+            self.player_tickets.insert(name.to_string(), self.map.add_ticket(ticket));
+        }
+        return Ok(player);
+    }
+
+    // The player data stays in the world.
+    pub fn leave_player(&mut self, name: &str) {
+        if let Some(ticket) = self.player_tickets.remove(name) {
+            self.map.remove_ticket(ticket);
+        }
+    }
+
+    pub fn is_online(&self, name: &str) -> bool {
+        return self.player_tickets.contains_key(name);
+    }
+
+    pub fn add_ticket(&mut self, ticket: Ticket) -> TicketId {
+        return self.map.add_ticket(ticket);
+    }
+
+    pub fn remove_ticket(&mut self, id: TicketId) -> Option<Ticket> {
+        return self.map.remove_ticket(id);
+    }
+
+    // Drops expired tickets and moves the ones of players to where they are now,
+    // then loads and unloads chunks to match.
+    pub fn update_chunks(&mut self) -> Result<(), WorldError> {
+        self.map.set_seed(self.level.seed);
+        self.map.tickets_mut().remove_expired(self.level.time);
+        for (name, ticket) in self.player_tickets.iter() {
+            if let Some(player) = self.players.get(name) {
+                self.map.move_ticket(*ticket, player.block_pos().chunk());
+            }
+        }
+        return self.map.update_chunks(&self.storage);
+    }
+
+    // Same as update_chunks, but blocks until every chunk the tickets need is resident.
+    pub fn wait_for_chunks(&mut self) -> Result<(), WorldError> {
+        self.update_chunks()?;
+        return self.map.wait_for_chunks(&self.storage);
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &PlayerData> {
+        return self.players.values();
+    }
+
+    pub fn get_block(&self, pos: BlockPos) -> Option<&Block> {
+        return self.map.block(pos);
+    }
+
+    pub fn set_block(&mut self, pos: BlockPos, block: Block) {
+        self.map.set_block(pos, block, &self.registry);
+    }
+
+    // None if the column is not loaded.
+    pub fn height(&self, kind: HeightmapKind, x: PosScalar, y: PosScalar) -> Option<PosScalar> {
+        return self.map.loaded_chunk(BlockPos::new(x, y, 0).chunk())?.height(kind, x, y);
+    }
+
+    pub fn blocks_in(&self, bounds: BlockBox) -> impl Iterator<Item = (BlockPos, &Block)> {
+        return self.map.blocks_in(bounds);
+    }
+
+    pub fn par_blocks_in(&self, bounds: BlockBox) -> impl ParallelIterator<Item = (BlockPos, &Block)> {
+        return self.map.par_blocks_in(bounds);
+    }
+
+    pub fn update_blocks_in<F>(&mut self, bounds: BlockBox, f: F)
+        where F: FnMut(BlockPos, &Block) -> Option<Block> {
+        self.map.update_blocks_in(bounds, &self.registry, f);
+    }
+
+    pub fn map(&self) -> &map::Map {
+        return &self.map;
+    }
+
+    pub fn map_mut(&mut self) -> &mut map::Map {
+        return &mut self.map;
+    }
+
+    // Pass the returned value to changed_since later on to find out what was modified in between.
+    pub fn revision(&self) -> u64 {
+        return chunk::current_revision();
+    }
+
+    pub fn changed_since(&self, revision: u64) -> impl Iterator<Item = SubChunkPos> + '_ {
+        return self.map.changed_since(revision);
+    }
+
+    pub fn tick_block_entities(&mut self, dt: std::time::Duration) {
+        self.map.tick_block_entities(dt);
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        return World::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::block_entity::{BlockEntity, ItemStack};
+    use crate::model::level::LEVEL_FORMAT_VERSION;
+    use crate::model::ticket::LoadLevel;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustcraft-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    // Chunks of the blocks used below
+    fn load_test_chunks(world: &mut World) {
+        world.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(-2, 2), 0));
+        world.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(31, -32), 0));
+        world.wait_for_chunks().unwrap();
+    }
+
+    #[test]
+    fn worlds_survive_save_and_load() {
+        let dir = temp_dir("world-save");
+        let mut world = World::new();
+        load_test_chunks(&mut world);
+        let stone = world.registry().block("stone").unwrap();
+        let furnace = world.registry().block("furnace").unwrap();
+        world.level_mut().seed = 1234;
+        world.level_mut().spawn = BlockPos::new(-20, 40, 5);
+        world.advance_time(std::time::Duration::from_secs(90));
+        world.set_block(BlockPos::new(-20, 40, 4), stone);
+        world.set_block(BlockPos::new(500, -500, 100), furnace);
+        if let Some(BlockEntity::Furnace(data)) = world.map_mut().loaded_chunk_mut(ChunkPos::new(31, -32)).unwrap().block_entity_mut(BlockPos::new(500, -500, 100)) {
+            data.fuel = Some(ItemStack::new("coal", 3));
+        }
+        world.join_player("alice").unwrap().health = 7.5;
+        world.save(&dir).unwrap();
+        assert_eq!(world.map().dirty_chunks().count(), 0);
+
+        let mut loaded = World::load(&dir).unwrap();
+        assert!(loaded.get_block(BlockPos::new(-20, 40, 4)).is_none());
+        load_test_chunks(&mut loaded);
+        assert_eq!(loaded.level(), world.level());
+        assert_eq!(loaded.level().time, 90_000);
+        assert_eq!(loaded.get_block(BlockPos::new(-20, 40, 4)), world.get_block(BlockPos::new(-20, 40, 4)));
+        assert_eq!(loaded.get_block(BlockPos::new(500, -500, 100)).unwrap().block_id(), furnace.block_id());
+        assert_eq!(loaded.map().loaded_chunk(ChunkPos::new(31, -32)).unwrap().block_entities(),
+                   world.map().loaded_chunk(ChunkPos::new(31, -32)).unwrap().block_entities());
+        assert_eq!(loaded.player("alice").unwrap().health, 7.5);
+        assert_eq!(loaded.height(HeightmapKind::Solid, -20, 40), Some(5));
+
+        // Saving again only writes what changed.
+        loaded.set_block(BlockPos::new(-20, 40, 4), Block::AIR);
+        loaded.save(&dir).unwrap();
+        let mut reloaded = World::load(&dir).unwrap();
+        load_test_chunks(&mut reloaded);
+        assert!(reloaded.get_block(BlockPos::new(-20, 40, 4)).unwrap().is_air());
+        assert_eq!(reloaded.get_block(BlockPos::new(500, -500, 100)).unwrap().block_id(), furnace.block_id());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_stream_around_players() {
+        let dir = temp_dir("world-stream");
+        let copy_dir = temp_dir("world-stream-copy");
+        let mut world = World::new();
+        let stone = world.registry().block("stone").unwrap();
+        let pos = BlockPos::new(3, 3, 3);
+        world.join_player("alice").unwrap();
+        world.wait_for_chunks().unwrap();
+        let extent = config::VIEW_RADIUS as PosScalar + 2;
+        assert_eq!(world.map().loaded_count(), ((2 * extent + 1) * (2 * extent + 1)) as usize);
+        assert_eq!(world.map().load_level(ChunkPos::new(0, 0)), Some(LoadLevel::EntityTicking));
+        world.set_block(pos, stone);
+
+        // Without a directory the changed chunk can't be unloaded
+        world.player_mut("alice").unwrap().position = [10_000.0, 0.0, 0.0];
+        world.wait_for_chunks().unwrap();
+        assert!(world.get_block(pos).is_some());
+        assert!(world.get_block(BlockPos::new(-100, 0, 0)).is_none());
+
+        world.save(&dir).unwrap();
+        world.set_block(pos, stone);
+        world.wait_for_chunks().unwrap();
+        assert!(world.get_block(pos).is_none());
+        assert!(world.get_block(BlockPos::new(10_000, 0, 0)).is_some());
+
+        // Chunks that aren't loaded are carried over to a new directory
+        world.save(&copy_dir).unwrap();
+        let mut copy = World::load(&copy_dir).unwrap();
+        copy.join_player("alice").unwrap().position = [0.0, 0.0, 0.0];
+        copy.wait_for_chunks().unwrap();
+        assert_eq!(copy.get_block(pos).unwrap().block_id(), stone.block_id());
+
+        copy.leave_player("alice");
+        copy.wait_for_chunks().unwrap();
+        assert_eq!(copy.map().loaded_count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&copy_dir).unwrap();
+    }
+
+    #[test]
+    fn reloaded_worlds_keep_their_generator() {
+        let dir = temp_dir("world-generator");
+        let mut world = World::new();
+        let mut settings = crate::model::level::GeneratorSettings{ name: "flat".to_string(), ..Default::default() };
+        settings.options.insert("layers".to_string(), "bedrock:1,stone:60,grass_block:1".to_string());
+        world.set_generator(generator::from_settings(&settings, &world.registry).unwrap());
+        world.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(0, 0), 0));
+        world.wait_for_chunks().unwrap();
+        assert_eq!(world.height(HeightmapKind::WorldSurface, 7, 7), Some(62));
+        world.save(&dir).unwrap();
+
+        // Chunks no one visited before the save are generated by the reloaded world
+        let mut loaded = World::load(&dir).unwrap();
+        assert_eq!(loaded.level().generator, settings);
+        loaded.add_ticket(Ticket::new(TicketKind::Plugin, ChunkPos::new(-9, 4), 0));
+        loaded.wait_for_chunks().unwrap();
+        for z in 0..64 {
+            assert_eq!(loaded.get_block(BlockPos::new(-140, 70, z)), world.get_block(BlockPos::new(2, 6, z)));
+        }
+
+        loaded.level_mut().generator.name = "caves".to_string();
+        loaded.save(&dir).unwrap();
+        assert!(matches!(World::load(&dir), Err(WorldError::Generator(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn created_worlds_spawn_on_the_ground() {
+        let mut world = World::create(99);
+        let spawn = world.level().spawn;
+        world.add_ticket(Ticket::new(TicketKind::Plugin, spawn.chunk(), 0));
+        world.wait_for_chunks().unwrap();
+        assert_eq!(world.height(HeightmapKind::MotionBlocking, spawn.x, spawn.y), Some(spawn.z));
+        assert!(!world.get_block(BlockPos::new(spawn.x, spawn.y, spawn.z - 1)).unwrap().is_air());
+        assert!(world.get_block(spawn).unwrap().is_air());
+    }
+
+    #[test]
+    fn tickets_expire_with_game_time() {
+        let mut world = World::new();
+        let mut ticket = Ticket::new(TicketKind::Forced, ChunkPos::new(5, 5), 0);
+        ticket.expires_at = Some(world.level().time + 1000);
+        world.add_ticket(ticket);
+        world.wait_for_chunks().unwrap();
+        assert_eq!(world.map().load_level(ChunkPos::new(5, 5)), Some(LoadLevel::Ticking));
+        world.advance_time(std::time::Duration::from_secs(1));
+        world.wait_for_chunks().unwrap();
+        assert_eq!(world.map().loaded_count(), 0);
+    }
+
+    #[test]
+    fn loading_reports_errors() {
+        let dir = temp_dir("world-errors");
+        assert!(matches!(World::load(&dir), Err(WorldError::NotAWorld(_))));
+
+        World::new().save(&dir).unwrap();
+        World::load(&dir).unwrap().save(&dir).unwrap();
+        std::fs::write(dir.join(LEVEL_FILE), "(seed: ").unwrap();
+        assert_eq!(World::load(&dir).unwrap().level().format_version, LEVEL_FORMAT_VERSION);
+        std::fs::write(dir.join(LEVEL_BACKUP_FILE), "(seed: ").unwrap();
+        assert!(matches!(World::load(&dir), Err(WorldError::Level(_))));
+
+        // A newer level file isn't replaced by the backup
+        std::fs::write(dir.join(LEVEL_BACKUP_FILE), ron::ser::to_string(&LevelData::new(0)).unwrap()).unwrap();
+        let mut future = LevelData::new(0);
+        future.format_version = LEVEL_FORMAT_VERSION + 1;
+        std::fs::write(dir.join(LEVEL_FILE), ron::ser::to_string(&future).unwrap()).unwrap();
+        assert!(matches!(World::load(&dir), Err(WorldError::UnsupportedVersion(_))));
+        std::fs::remove_file(dir.join(LEVEL_FILE)).unwrap();
+        assert_eq!(World::load(&dir).unwrap().level().format_version, LEVEL_FORMAT_VERSION);
+
+        assert!(matches!(World::new().join_player("../escape"), Err(WorldError::InvalidPlayerName(_))));
+        // Player files must hold a valid name, the one of the file
+        let player = |name: &str| ron::ser::to_string(&PlayerData::new(name, [0.0, 0.0, 0.0])).unwrap();
+        std::fs::write(dir.join(PLAYERS_DIR).join("bob.ron"), player("alice")).unwrap();
+        assert!(matches!(World::load(&dir), Err(WorldError::Player(_, _))));
+        std::fs::remove_file(dir.join(PLAYERS_DIR).join("bob.ron")).unwrap();
+        std::fs::write(dir.join(PLAYERS_DIR).join("a b.ron"), player("a b")).unwrap();
+        assert!(matches!(World::load(&dir), Err(WorldError::InvalidPlayerName(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Set by whoever gave up on a task. Long running work can check it to stop early.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        return CancelToken::default();
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::Relaxed);
+    }
+
+    // True for clones of the same token.
+    pub fn same_as(&self, other: &CancelToken) -> bool {
+        return Arc::ptr_eq(&self.cancelled, &other.cancelled);
+    }
+}
+
+type Work<T> = Box<dyn FnOnce(&CancelToken) -> Option<T> + Send>;
+
+// Work identified by a key, returning None if it stopped because of the token.
+pub struct Task<K, T> {
+    pub key: K,
+    pub token: CancelToken,
+    work: Work<T>,
+}
+
+impl<K, T> Task<K, T> {
+    pub fn new<F>(key: K, work: F) -> Self
+        where F: FnOnce(&CancelToken) -> Option<T> + Send + 'static {
+        return Task{ key, token: CancelToken::new(), work: Box::new(work) };
+    }
+
+    // The result is None if the task was cancelled before it started or while it ran,
+    // and if the work panicked.
+    pub fn run(self) -> (K, CancelToken, Option<T>) {
+        let Task{ key, token, work } = self;
+        if token.is_cancelled() {
+            return (key, token, None);
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| work(&token))).unwrap_or(None);
+        return (key, token, result);
+    }
+}
@@ -0,0 +1,174 @@
+use super::task::{CancelToken, Task};
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Finished<K, T> = (K, CancelToken, Option<T>);
+
+// Runs tasks on worker threads, their results are collected by poll on the thread owning the queue.
+// There is at most one pending task per key, submitting another one for the same key does nothing.
+pub struct TaskQueue<K, T> {
+    sender: Option<Sender<Task<K, T>>>,
+    results: Receiver<Finished<K, T>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: HashMap<K, CancelToken>,
+    // Finished while waiting, handed out by the next poll
+    ready: Vec<(K, T)>,
+}
+
+impl<K, T> TaskQueue<K, T> where K: Eq + Hash + Clone + Send + 'static, T: Send + 'static {
+    pub fn new(name: &str, threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Task<K, T>>();
+        let (result_sender, results) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1)).map(|idx| {
+            let receiver = receiver.clone();
+            let result_sender = result_sender.clone();
+            return thread::Builder::new().name(format!("{}-{}", name, idx)).spawn(move || loop {
+                let task = match receiver.lock().unwrap().recv() {
+                    Ok(task) => task,
+                    Err(_) => { return; }
+                };
+                if result_sender.send(task.run()).is_err() {
+                    return;
+                }
+            }).unwrap();
+        }).collect();
+
+        return TaskQueue{ sender: Some(sender), results, workers, pending: HashMap::new(), ready: Vec::new() };
+    }
+
+    // Returns false if a task with the key is already pending.
+    pub fn submit<F>(&mut self, key: K, work: F) -> bool
+        where F: FnOnce(&CancelToken) -> Option<T> + Send + 'static {
+        if self.pending.contains_key(&key) {
+            return false;
+        }
+        let task = Task::new(key.clone(), work);
+        self.pending.insert(key, task.token.clone());
+        self.sender.as_ref().unwrap().send(task).unwrap();
+        return true;
+    }
+
+    // The result of a cancelled task is never returned, even if it already finished.
+    pub fn cancel(&mut self, key: &K) -> bool {
+        return match self.pending.remove(key) {
+            Some(token) => { token.cancel(); true }
+            None => false,
+        };
+    }
+
+    pub fn is_pending(&self, key: &K) -> bool {
+        return self.pending.contains_key(key);
+    }
+
+    pub fn pending_count(&self) -> usize {
+        return self.pending.len();
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &K> {
+        return self.pending.keys();
+    }
+
+    fn finish(&mut self, finished: Finished<K, T>) {
+        let (key, token, result) = finished;
+        if !self.pending.get(&key).is_some_and(|t| t.same_as(&token)) {
+            return;
+        }
+        self.pending.remove(&key);
+        if let Some(result) = result {
+            self.ready.push((key, result));
+        }
+    }
+
+    // Results of the tasks that finished since the last call. Tasks that panicked are
+    // no longer pending, but have no result.
+    pub fn poll(&mut self) -> Vec<(K, T)> {
+        while let Ok(finished) = self.results.try_recv() {
+            self.finish(finished);
+        }
+        return std::mem::take(&mut self.ready);
+    }
+
+    // Blocks until one of the pending tasks finishes, returns right away if there are none.
+    pub fn wait(&mut self) {
+        let pending = self.pending.len();
+        while !self.pending.is_empty() && self.pending.len() == pending {
+            match self.results.recv() {
+                Ok(finished) => self.finish(finished),
+                Err(_) => { return; }
+            }
+        }
+    }
+}
+
+impl<K, T> Drop for TaskQueue<K, T> {
+    // Tasks that didn't start yet are skipped, running ones are waited for.
+    fn drop(&mut self) {
+        for token in self.pending.values() {
+            token.cancel();
+        }
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn tasks_are_deduplicated_and_cancelled() {
+        let mut queue = TaskQueue::new("test", 1);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+
+        // The only worker is busy with the first task until it's released
+        let counter = runs.clone();
+        assert!(queue.submit(1, move |_| { gate.lock().unwrap().recv().unwrap(); counter.fetch_add(1, Ordering::SeqCst); Some(10) }));
+        for key in 2..4 {
+            let counter = runs.clone();
+            assert!(queue.submit(key, move |_| { counter.fetch_add(1, Ordering::SeqCst); Some(key * 10) }));
+        }
+        assert!(!queue.submit(2, |_| Some(0)));
+        assert!(queue.cancel(&2));
+        assert!(!queue.cancel(&2));
+        assert_eq!(queue.pending_count(), 2);
+
+        release.send(()).unwrap();
+        let mut results = Vec::new();
+        while queue.pending_count() > 0 {
+            queue.wait();
+            results.extend(queue.poll());
+        }
+        results.sort();
+        assert_eq!(results, vec![(1, 10), (3, 30)]);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn workers_survive_panics() {
+        let mut queue = TaskQueue::new("test", 2);
+        queue.submit("panics", |_| -> Option<u32> { panic!("task failed") });
+        queue.wait();
+        assert!(queue.poll().is_empty());
+        assert!(!queue.is_pending(&"panics"));
+
+        for idx in 0..4 {
+            queue.submit(if idx % 2 == 0 { "even" } else { "odd" }, move |token| if token.is_cancelled() { None } else { Some(idx) });
+        }
+        while queue.pending_count() > 0 {
+            queue.wait();
+        }
+        let mut results = queue.poll();
+        results.sort();
+        assert_eq!(results, vec![("even", 0), ("odd", 1)]);
+    }
+}
@@ -0,0 +1,140 @@
+use super::{derive_seed, Noise};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FractalKind {
+    // Octaves added up, rolling hills
+    Fbm,
+    // Octaves folded into sharp crests, mountain ridges and ravines
+    Ridged,
+    // Absolute value of the octaves, puffy shapes like clouds or cave networks
+    Billow,
+}
+
+// Several octaves of a noise, each at a higher frequency and a lower amplitude than the previous
+// one. Every octave has its own seed so the lattices of the octaves don't line up.
+pub struct Fractal<N: Noise> {
+    pub kind: FractalKind,
+    octaves: Vec<N>,
+    // Of the first octave
+    pub frequency: f64,
+    // Frequency multiplier between octaves
+    pub lacunarity: f64,
+    // Amplitude multiplier between octaves
+    pub gain: f64,
+}
+
+impl<N: Noise> Fractal<N> {
+    pub fn new<F: Fn(u64) -> N>(kind: FractalKind, octaves: usize, seed: u64, make: F) -> Self {
+        let octaves = (0..octaves).map(|i| make(derive_seed(seed, i as u64))).collect();
+        return Fractal{ kind, octaves, frequency: 1.0, lacunarity: 2.0, gain: 0.5 };
+    }
+
+    pub fn octaves(&self) -> usize {
+        return self.octaves.len();
+    }
+
+    // Normalized so the result stays roughly in [-1, 1] whatever the number of octaves.
+    fn combine<F: Fn(&N, f64) -> f64>(&self, sample: F) -> f64 {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.frequency, 1.0);
+        for octave in self.octaves.iter() {
+            let value = sample(octave, frequency);
+            let value = match self.kind {
+                FractalKind::Fbm => value,
+                FractalKind::Ridged => {
+                    let ridge = 1.0 - value.abs();
+                    ridge * ridge * 2.0 - 1.0
+                }
+                FractalKind::Billow => value.abs() * 2.0 - 1.0,
+            };
+            sum += value * amplitude;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        if total == 0.0 {
+            return 0.0;
+        }
+        return sum / total;
+    }
+}
+
+impl<N: Noise> Noise for Fractal<N> {
+    fn sample2(&self, x: f64, y: f64) -> f64 {
+        return self.combine(|noise, f| noise.sample2(x * f, y * f));
+    }
+
+    fn sample3(&self, x: f64, y: f64, z: f64) -> f64 {
+        return self.combine(|noise, f| noise.sample3(x * f, y * f, z * f));
+    }
+}
+
+// Moves the sample position by another noise before sampling, turns the blobs of a noise into
+// swirls and overhangs.
+pub struct DomainWarp<N: Noise, W: Noise> {
+    source: N,
+    // One offset noise per axis
+    warp: [W; 3],
+    // Largest offset, in the units of the sample position
+    pub amplitude: f64,
+}
+
+impl<N: Noise, W: Noise> DomainWarp<N, W> {
+    pub fn new<F: Fn(u64) -> W>(source: N, seed: u64, amplitude: f64, make: F) -> Self {
+        let warp = [make(derive_seed(seed, 0)), make(derive_seed(seed, 1)), make(derive_seed(seed, 2))];
+        return DomainWarp{ source, warp, amplitude };
+    }
+}
+
+impl<N: Noise, W: Noise> Noise for DomainWarp<N, W> {
+    fn sample2(&self, x: f64, y: f64) -> f64 {
+        let dx = self.warp[0].sample2(x, y) * self.amplitude;
+        let dy = self.warp[1].sample2(x, y) * self.amplitude;
+        return self.source.sample2(x + dx, y + dy);
+    }
+
+    fn sample3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let dx = self.warp[0].sample3(x, y, z) * self.amplitude;
+        let dy = self.warp[1].sample3(x, y, z) * self.amplitude;
+        let dz = self.warp[2].sample3(x, y, z) * self.amplitude;
+        return self.source.sample3(x + dx, y + dy, z + dz);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::noise::{Perlin, Simplex};
+
+    fn samples<N: Noise>(noise: &N) -> Vec<u64> {
+        return [(0.3, 0.7, 0.1), (-13.3, 7.9, 3.3), (1000.1, -2000.7, 64.9)].iter()
+            .flat_map(|(x, y, z)| vec![noise.sample2(*x, *y).to_bits(), noise.sample3(*x, *y, *z).to_bits()])
+            .collect();
+    }
+
+    #[test]
+    fn golden_values() {
+        // Bit patterns of the samples, if these change so does every world generated from a seed
+        let mut fbm = Fractal::new(FractalKind::Fbm, 4, 1234, Perlin::new);
+        fbm.frequency = 0.01;
+        assert_eq!(samples(&fbm), vec![0x3f720cfec900318a, 0x3f609002168a0700, 0x3fcac72a98fc887a, 0xbfad05b4272ab9e7, 0x3f40d14e0b88cfa6, 0x3fc8eb42ab28d4f3]);
+        let ridged = Fractal::new(FractalKind::Ridged, 3, 1234, Simplex::new);
+        assert_eq!(samples(&ridged), vec![0xbfe70058b26c9cab, 0x3fc0fe32c5d60708, 0xbfc1950c2c052df9, 0x3fd9c239f8ed5525, 0xbfde58da6d9944b1, 0xbfd410712524e5df]);
+        let billow = Fractal::new(FractalKind::Billow, 3, 1234, Perlin::new);
+        assert_eq!(samples(&billow), vec![0xbfe770b7c5da94a0, 0xbf830b9a06f40fe5, 0xbfd370cbe76c01f7, 0xbfe05b3e6e8ea5e3, 0xbfdf7803966111c3, 0xbfea4947f79f204f]);
+        let warped = DomainWarp::new(Simplex::new(1234), 99, 4.0, Perlin::new);
+        assert_eq!(samples(&warped), vec![0x3fc4e5dc2397e3c2, 0xbfc6dbaf4ce8f62f, 0x3fdafceabcfc1b8e, 0xbfe1f7754374aa6f, 0x3fe1538588dc7572, 0x3fec741fa0318740]);
+    }
+
+    #[test]
+    fn octaves_are_normalized() {
+        for kind in [FractalKind::Fbm, FractalKind::Ridged, FractalKind::Billow].iter() {
+            let noise = Fractal::new(*kind, 6, 3, Simplex::new);
+            for i in 0..2000 {
+                let value = noise.sample3(i as f64 * 0.31, i as f64 * -0.17, i as f64 * 0.07);
+                assert!(value.abs() <= 1.0, "{:?} gave {}", kind, value);
+            }
+        }
+        assert_eq!(Fractal::new(FractalKind::Fbm, 0, 3, Perlin::new).sample2(0.5, 0.5), 0.0);
+    }
+}
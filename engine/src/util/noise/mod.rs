@@ -0,0 +1,104 @@
+pub mod perlin;
+pub mod simplex;
+pub mod fractal;
+
+pub use fractal::{DomainWarp, Fractal, FractalKind};
+pub use perlin::Perlin;
+pub use simplex::Simplex;
+
+// Two servers with the same seed must generate the same world, so everything here is computed with
+// plain f64 arithmetic and floor, which IEEE 754 rounds the same way everywhere. No transcendental
+// functions, no fused multiply-add and no state shared between calls, sampling order doesn't matter.
+
+// Gradient noise, roughly in [-1, 1].
+pub trait Noise: Send + Sync {
+    fn sample2(&self, x: f64, y: f64) -> f64;
+    fn sample3(&self, x: f64, y: f64, z: f64) -> f64;
+}
+
+// Seeds of independent noise sources derived from the world seed, e.g. one per octave or per
+// terrain feature. Different salts give unrelated seeds.
+pub fn derive_seed(seed: u64, salt: u64) -> u64 {
+    return SeedRng::new(seed ^ SeedRng::new(salt).next_u64()).next_u64();
+}
+
+// splitmix64, shuffles permutation tables and places features like cave tunnels.
+pub struct SeedRng {
+    state: u64,
+}
+
+impl SeedRng {
+    pub fn new(seed: u64) -> Self {
+        return SeedRng{ state: seed };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        return z ^ (z >> 31);
+    }
+
+    // Uniform in [0, bound), bound is small enough that the modulo bias doesn't matter.
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        return self.next_u64() % bound;
+    }
+
+    // Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+// 0..256 shuffled, repeated once so lookups of index + 1 don't need to wrap.
+pub(crate) fn permutation(seed: u64) -> [u8; 512] {
+    let mut table = [0u8; 512];
+    for (i, value) in table.iter_mut().take(256).enumerate() {
+        *value = i as u8;
+    }
+    let mut rng = SeedRng::new(seed);
+    for i in (1..256).rev() {
+        let j = rng.next_below(i as u64 + 1) as usize;
+        table.swap(i, j);
+    }
+    for i in 0..256 {
+        table[i + 256] = table[i];
+    }
+    return table;
+}
+
+// Lattice cell of a coordinate, wrapped to the size of the permutation table.
+pub(crate) fn cell(v: f64) -> (usize, f64) {
+    let floor = v.floor();
+    return (((floor as i64) & 255) as usize, v - floor);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn seeds_are_derived_deterministically() {
+        assert_eq!(SeedRng::new(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(derive_seed(42, 1), derive_seed(42, 1));
+        assert_ne!(derive_seed(42, 1), derive_seed(42, 2));
+        assert_ne!(derive_seed(42, 1), derive_seed(43, 1));
+
+        let table = permutation(7);
+        let mut sorted = table[..256].to_vec();
+        sorted.sort();
+        assert!(sorted.iter().enumerate().all(|(i, v)| i == *v as usize));
+        assert_eq!(table[..256], table[256..]);
+    }
+
+    #[test]
+    fn sampling_does_not_depend_on_threads() {
+        let noise = Fractal::new(FractalKind::Fbm, 5, 99, Simplex::new);
+        let points: Vec<(f64, f64, f64)> = (0..4096).map(|i| (i as f64 * 0.37 - 700.0, i as f64 * -1.13, (i % 64) as f64 * 0.5)).collect();
+        let serial: Vec<u64> = points.iter().map(|(x, y, z)| noise.sample3(*x, *y, *z).to_bits()).collect();
+        let parallel: Vec<u64> = points.par_iter().map(|(x, y, z)| noise.sample3(*x, *y, *z).to_bits()).collect();
+        assert_eq!(serial, parallel);
+    }
+}
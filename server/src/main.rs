@@ -17,4 +17,5 @@ fn main() {
         app.update(dt);
         last_tick_instant = now_instant;
     }
+    app.shutdown();
 }
\ No newline at end of file
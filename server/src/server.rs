@@ -1,21 +1,64 @@
+use rustcraft_engine::core::PosScalar;
 use rustcraft_engine::engine::Engine;
 use rustcraft_engine::model::World;
+use rustcraft_engine::model::world::WorldError;
+use rustcraft_engine::model::ticket::{Ticket, TicketKind};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WORLD_DIR: &str = "world";
+const SPAWN_RADIUS: PosScalar = 4; // In chunks, kept loaded without players
+const CHUNK_MEMORY_BUDGET: usize = 512 * 1024 * 1024; // Chunks no one needs stay cached up to this many bytes
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct ServerApp {
     is_running : bool,
+    // Set by SIGINT or SIGTERM, the server stops after the current update and saves the world
+    stop_requested: Arc<AtomicBool>,
     engine: Engine,
     world: Option<World>,
+    // Time since the world was last saved
+    unsaved: Duration,
 }
 
 impl ServerApp {
     pub fn new() -> Self {
         let engine = Engine::new();
-        let world = World::new();
+        let mut world = match World::load(Path::new(WORLD_DIR)) {
+            Ok(world) => world,
+            Err(WorldError::NotAWorld(_)) => {
+                // Saved right away, so the world has a directory to unload chunks to
+                let mut world = World::create(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64));
+                if let Err(error) = world.save(Path::new(WORLD_DIR)) {
+                    panic!("Failed to create the world: {:?}", error);
+                }
+                world
+            }
+            Err(error) => panic!("Failed to load the world: {:?}", error),
+        };
+        let spawn = world.level().spawn.chunk();
+        world.add_ticket(Ticket::new(TicketKind::Spawn, spawn, SPAWN_RADIUS));
+        world.map_mut().set_memory_budget(Some(CHUNK_MEMORY_BUDGET));
+
+        // A second signal while the server is still stopping kills it right away
+        let stop_requested = Arc::new(AtomicBool::new(false));
+        for signal in [SIGINT, SIGTERM].iter() {
+            signal_hook::flag::register_conditional_shutdown(*signal, 1, stop_requested.clone())
+                .and_then(|_| signal_hook::flag::register(*signal, stop_requested.clone()))
+                .expect("Failed to install the signal handlers");
+        }
 
         return ServerApp{
             is_running: true,
+            stop_requested,
             engine,
-            world: None,
+            world: Some(world),
+            unsaved: Duration::from_secs(0),
         };
     }
 
@@ -24,9 +67,36 @@ impl ServerApp {
     }
 
     pub fn update(&mut self, dt: std::time::Duration) {
+        if self.stop_requested.load(Ordering::Relaxed) {
+            self.is_running = false;
+            return;
+        }
         println!("Server update: dt={:?}", dt);
-        //self.engine.update(&mut self.world, dt);
-        std::thread::sleep_ms(1000);
+        if let Some(world) = self.world.as_mut() {
+            // Chunks that failed to load stay missing until the tickets change, the server keeps running
+            if let Err(error) = self.engine.update(world, dt) {
+                eprintln!("Failed to update the world: {:?}", error);
+            }
+            self.unsaved += dt;
+            if self.unsaved >= SAVE_INTERVAL {
+                self.save();
+            }
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    // Errors are reported and the server keeps running, the next save tries again.
+    fn save(&mut self) {
+        if let Some(world) = self.world.as_mut() {
+            match world.save(Path::new(WORLD_DIR)) {
+                Ok(()) => { self.unsaved = Duration::from_secs(0); }
+                Err(error) => eprintln!("Failed to save the world: {:?}", error),
+            }
+        }
+    }
 
+    pub fn shutdown(&mut self) {
+        self.save();
+        self.world = None;
     }
 }
\ No newline at end of file